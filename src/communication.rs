@@ -1,34 +1,234 @@
-use crate::components::TowerType;
+use crate::components::{TargetingMode, TowerType};
+use crate::query::QueryResult;
+use crossbeam_channel::Sender;
 use hexx::Hex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateTower {
     pub tower_type: TowerType,
     pub hex_pos: Hex,
 }
 
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct UpgradeTower {
+    pub hex_pos: Hex,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct SellTower {
+    pub hex_pos: Hex,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct SetTargeting {
+    pub hex_pos: Hex,
+    pub mode: TargetingMode,
+}
+
 #[derive(Debug)]
 pub struct GameOver;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct ToggleTile {
     pub hex_pos: Hex,
 }
 #[derive(Debug)]
 pub struct RefreshTowerDamage;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Restart;
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveGame {
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadGame {
+    pub path: String,
+}
+
 #[derive(Debug)]
 pub struct RecalculateEnemyPaths;
 
+// What outside clients can ask about the game, answered with a `QueryResult`
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum TDQuery {
+    // budget, scores, lives and progress
+    State,
+    Tile(Hex),
+    Enemies,
+    Towers,
+    Paths,
+}
+
+// Why a command was not carried out
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommandError {
+    OutOfBounds(Hex),
+    // the goal, a spawn or a tile with a tower already
+    TileOccupied(Hex),
+    // blocking the tile would leave a spawn without a way to the goal
+    DisconnectsSpawn(Hex),
+    InsufficientBudget { budget: u32, cost: u32 },
+    // with the names of the kinds there are
+    UnknownTowerType { name: String, kinds: Vec<String> },
+    // upgrading, selling or targeting a tile without a tower
+    NoTower(Hex),
+    FullyUpgraded(Hex),
+    // the snapshot file could not be written or read
+    Snapshot(String),
+    // sent on the same tick as a restart
+    Restarting,
+    // a json request that could not be parsed
+    InvalidRequest(String),
+    // sent while the game is paused or over
+    GameNotRunning,
+    // a load replaced by a later one of the same tick
+    Superseded,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::OutOfBounds(hex) => write!(f, "{},{} is not on the board", hex.x, hex.y),
+            Self::TileOccupied(hex) => write!(f, "{},{} is occupied", hex.x, hex.y),
+            Self::DisconnectsSpawn(hex) => write!(
+                f,
+                "blocking {},{} would cut a spawn off from the goal",
+                hex.x, hex.y
+            ),
+            Self::InsufficientBudget { budget, cost } => {
+                write!(f, "costs {cost} but the budget is {budget}")
+            }
+            Self::UnknownTowerType { name, kinds } => write!(
+                f,
+                "there is no tower type {name}, valid kinds are {}",
+                kinds.join(", ")
+            ),
+            Self::NoTower(hex) => write!(f, "there is no tower on {},{}", hex.x, hex.y),
+            Self::FullyUpgraded(hex) => {
+                write!(f, "the tower on {},{} is fully upgraded", hex.x, hex.y)
+            }
+            Self::Snapshot(error) => write!(f, "{error}"),
+            Self::Restarting => write!(f, "the game restarted first"),
+            Self::InvalidRequest(error) => write!(f, "invalid request: {error}"),
+            Self::GameNotRunning => write!(f, "the game is paused or over"),
+            Self::Superseded => write!(f, "a later load of the same tick replaced it"),
+        }
+    }
+}
+
+// Where the outcome of a command is sent to, for clients that asked for one
+#[derive(Debug, Clone)]
+pub struct Reply {
+    // id the client gave the command, none for queries in the line format
+    pub id: Option<u64>,
+    pub tx: Sender<CommandResponse>,
+}
+
+// A command on its way through the game, together with where to reply to
+#[derive(Debug, Clone)]
+pub struct Requested<T> {
+    pub command: T,
+    pub reply: Option<Reply>,
+}
+
+impl<T> Requested<T> {
+    // a command no one waits on the outcome of
+    pub fn new(command: T) -> Self {
+        Self {
+            command,
+            reply: None,
+        }
+    }
+
+    // passes the reply on to the command this one is carried out by
+    pub fn forward<U>(&self, command: U) -> Requested<U> {
+        Requested {
+            command,
+            reply: self.reply.clone(),
+        }
+    }
+
+    pub fn accept(&self) -> CommandResult {
+        CommandResult {
+            reply: self.reply.clone(),
+            result: Ok(()),
+        }
+    }
+
+    pub fn reject(&self, error: CommandError) -> CommandResult {
+        CommandResult {
+            reply: self.reply.clone(),
+            result: Err(error),
+        }
+    }
+}
+
+// sent by the systems carrying out commands once they did or refused one
+#[derive(Debug, Clone)]
+pub struct CommandResult {
+    pub reply: Option<Reply>,
+    pub result: Result<(), CommandError>,
+}
+
+// Json command of a client, e.g. `{"id": 1, "command": {"Toggle": {"hex_pos": {"x": 1, "y": 2}}}}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandRequest {
+    pub id: u64,
+    pub command: TDCommand,
+}
+
+// Json answer to a request, without an error if the command was carried out,
+// the id is missing when it could not be read from the request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandResponse {
+    pub id: Option<u64>,
+    pub error: Option<CommandError>,
+    // what was asked for by a query
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<QueryResult>,
+}
+
 // outside communication
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TDCommand {
     Toggle(ToggleTile),
     Restart(Restart),
     Tower(CreateTower),
+    Upgrade(UpgradeTower),
+    Sell(SellTower),
+    Target(SetTargeting),
+    Save(SaveGame),
+    Load(LoadGame),
+    // answered without changing the game, not recorded in replays
+    Query(TDQuery),
+}
+
+impl TDCommand {
+    // whether the command is carried out while the game is paused or over,
+    // the others are refused until the game runs again
+    pub fn outside_game(&self) -> bool {
+        matches!(
+            self,
+            TDCommand::Query(_) | TDCommand::Save(_) | TDCommand::Load(_)
+        )
+    }
+}
+
+// reads a json request, answering the ones that can not be read right away
+#[allow(clippy::result_large_err)]
+pub fn parse_request(input: &str) -> Result<CommandRequest, CommandResponse> {
+    serde_json::from_str(input).map_err(|e| CommandResponse {
+        id: serde_json::from_str::<serde_json::Value>(input)
+            .ok()
+            .and_then(|value| value.get("id")?.as_u64()),
+        error: Some(CommandError::InvalidRequest(e.to_string())),
+        data: None,
+    })
 }
 
 pub fn parse_command(input: &str) -> Option<TDCommand> {
@@ -37,29 +237,58 @@ pub fn parse_command(input: &str) -> Option<TDCommand> {
     match *command {
         "reset" => Some(TDCommand::Restart(Restart)),
         "toggle" => {
-            let values = split.get(1)?;
-            let (x, y) = values.split_once(',')?;
-            let (x, y) = (x.parse().ok()?, y.parse().ok()?);
-            Some(TDCommand::Toggle(ToggleTile {
-                hex_pos: Hex { x, y },
-            }))
+            let hex_pos = parse_hex(split.get(1)?)?;
+            Some(TDCommand::Toggle(ToggleTile { hex_pos }))
         }
         "tower" => {
-            let values = split.get(1)?;
-            let (x, y) = values.split_once(',')?;
-            let (x, y) = (x.parse().ok()?, y.parse().ok()?);
-            let tower_type = match *split.get(2)? {
-                "s" => TowerType::Small,
-                "m" => TowerType::Medium,
-                "l" => TowerType::Large,
-                _ => return None,
-            };
+            let hex_pos = parse_hex(split.get(1)?)?;
+            let tower_type = TowerType(split.get(2)?.to_string());
 
             Some(TDCommand::Tower(CreateTower {
-                hex_pos: Hex { x, y },
+                hex_pos,
                 tower_type,
             }))
         }
+        "upgrade" => {
+            let hex_pos = parse_hex(split.get(1)?)?;
+            Some(TDCommand::Upgrade(UpgradeTower { hex_pos }))
+        }
+        "sell" => {
+            let hex_pos = parse_hex(split.get(1)?)?;
+            Some(TDCommand::Sell(SellTower { hex_pos }))
+        }
+        "target" => {
+            let hex_pos = parse_hex(split.get(1)?)?;
+            let mode = match *split.get(2)? {
+                "first" => TargetingMode::First,
+                "last" => TargetingMode::Last,
+                "strongest" => TargetingMode::Strongest,
+                "weakest" => TargetingMode::Weakest,
+                "closest" => TargetingMode::Closest,
+                _ => return None,
+            };
+            Some(TDCommand::Target(SetTargeting { hex_pos, mode }))
+        }
+        "save" => Some(TDCommand::Save(SaveGame {
+            path: split.get(1)?.to_string(),
+        })),
+        "load" => Some(TDCommand::Load(LoadGame {
+            path: split.get(1)?.to_string(),
+        })),
+        "state" => Some(TDCommand::Query(TDQuery::State)),
+        "tile" => {
+            let hex_pos = parse_hex(split.get(1)?)?;
+            Some(TDCommand::Query(TDQuery::Tile(hex_pos)))
+        }
+        "enemies" => Some(TDCommand::Query(TDQuery::Enemies)),
+        "towers" => Some(TDCommand::Query(TDQuery::Towers)),
+        "paths" => Some(TDCommand::Query(TDQuery::Paths)),
         _ => None,
     }
 }
+
+// coordinates written as `x,y`
+fn parse_hex(values: &str) -> Option<Hex> {
+    let (x, y) = values.split_once(',')?;
+    Some(Hex::new(x.parse().ok()?, y.parse().ok()?))
+}
@@ -1,9 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use crate::utils::*;
+use crate::{config::WaveConfig, flow_field::FlowField, utils::*};
 use bevy::{
     prelude::{Component, Entity},
-    time::Timer,
+    tasks::Task,
+    time::{Timer, TimerMode},
 };
 use hexx::{Hex, HexLayout};
 use serde::{Deserialize, Serialize};
@@ -41,10 +42,92 @@ pub struct ScoreBoard {
 #[derive(Debug, Component, Default)]
 pub struct GameTimer(pub Timer);
 
-#[derive(Debug, Component)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    Victory,
+    Defeat,
+}
+
+// How the game ended, none while it is still going
+#[derive(Debug, Component, Default)]
+pub struct GameResult(pub Option<Outcome>);
+
+// Progress through the scripted waves of the config
+#[derive(Debug, Component, Default)]
+pub struct WaveState {
+    // index of the wave being spawned, or waited for once fully spawned
+    pub wave: usize,
+    // enemies of the current wave spawned so far
+    pub spawned: u32,
+    // until the next enemy, or until the next wave after the last enemy
+    pub timer: Timer,
+}
+
+impl WaveState {
+    pub fn new(waves: &[WaveConfig]) -> Self {
+        let interval = waves.first().map_or(0., |wave| wave.spawn_interval);
+        Self {
+            wave: 0,
+            spawned: 0,
+            timer: Timer::from_seconds(interval, TimerMode::Once),
+        }
+    }
+
+    // whether every enemy of every wave has been spawned
+    pub fn finished(&self, waves: &[WaveConfig]) -> bool {
+        match waves.get(self.wave) {
+            Some(wave) => self.wave + 1 == waves.len() && self.spawned >= wave.enemy_count(),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Component, Clone, Serialize, Deserialize)]
 pub struct TDPaths {
     pub spawns: Vec<Hex>,
-    pub paths: Option<HashMap<usize, Vec<Hex>>>,
+    pub goal: Hex,
+    // the way from each spawn to the goal along the flow field
+    pub paths: Option<BTreeMap<usize, Vec<Hex>>>,
+    pub flow_field: FlowField,
+}
+
+// Flow field and spawn paths calculated in the background
+#[derive(Debug, Default)]
+pub struct CalculatedPaths {
+    pub flow_field: FlowField,
+    pub paths: BTreeMap<usize, Vec<Hex>>,
+}
+
+// Paths being calculated, the current ones stay in use until they are applied
+#[derive(Debug, Component)]
+pub struct PendingPaths {
+    pub calculation: PathCalculation,
+    // tick the paths are applied on, the simulation waits for the calculation if needed
+    pub due: u64,
+}
+
+// Paths due on a later tick are calculated in the background, the others right away
+#[derive(Debug)]
+pub enum PathCalculation {
+    Running(Task<CalculatedPaths>),
+    Done(CalculatedPaths),
+}
+
+impl TDPaths {
+    // spawns that currently have a way to the goal
+    pub fn connected_spawns(&self) -> Vec<Hex> {
+        self.spawns
+            .iter()
+            .copied()
+            .filter(|spawn| self.flow_field.cost(*spawn).is_some())
+            .collect()
+    }
+
+    // the goal or a spawn, blocking those is only ever
+    // checked against the full flow field
+    pub fn is_endpoint(&self, hex: Hex) -> bool {
+        hex == self.goal || self.spawns.contains(&hex)
+    }
 }
 
 // Text
@@ -61,6 +144,10 @@ pub struct HasTower;
 #[derive(Debug, Component)]
 pub struct IsGoal;
 
+// Lives left on the goal tile
+#[derive(Debug, Component)]
+pub struct Lives(pub u32);
+
 #[derive(Debug, Component)]
 pub struct IsSpawn;
 
@@ -70,9 +157,44 @@ pub struct OnPath;
 #[derive(Debug, Component)]
 pub struct Refresh;
 
-#[derive(Debug, Component)]
+// damage dealt on every damaging tick to enemies on a path tile,
+// added up from the area towers in range of it
+#[derive(Debug, Component, Default)]
 pub struct Damaging {
     pub value: u32,
+    // damage by damage type, adding up to `value`
+    pub damage: BTreeMap<String, u32>,
+    // status effects put on enemies on the tile, with the number of towers putting them
+    pub effects: BTreeMap<String, u32>,
+}
+
+impl Damaging {
+    pub fn add(&mut self, damage: u32, damage_type: &str, effect: Option<&String>) {
+        self.value += damage;
+        if damage > 0 {
+            *self.damage.entry(damage_type.to_string()).or_default() += damage;
+        }
+        if let Some(effect) = effect {
+            *self.effects.entry(effect.clone()).or_default() += 1;
+        }
+    }
+
+    pub fn remove(&mut self, damage: u32, damage_type: &str, effect: Option<&String>) {
+        self.value = self.value.saturating_sub(damage);
+        if let Some(left) = self.damage.get_mut(damage_type) {
+            *left = left.saturating_sub(damage);
+        }
+        self.damage.retain(|_, left| *left > 0);
+        if let Some(towers) = effect.and_then(|effect| self.effects.get_mut(effect)) {
+            *towers -= 1;
+        }
+        self.effects.retain(|_, towers| *towers > 0);
+    }
+
+    // no tower in range deals damage or puts effects on the tile anymore
+    pub fn is_empty(&self) -> bool {
+        self.value == 0 && self.effects.is_empty()
+    }
 }
 
 #[derive(Debug, Component)]
@@ -84,7 +206,7 @@ pub struct Tile {
     pub is_cursor: bool,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Component)]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Component, Serialize, Deserialize)]
 pub enum TileType {
     Plains,
     Mountain,
@@ -100,48 +222,90 @@ impl TileType {
 }
 
 // Enemies
-#[derive(Debug, Component)]
+#[derive(Debug, Component, Clone, Serialize, Deserialize)]
 pub struct Enemy {
     pub health: u32,
+    pub max_health: u32,
     pub value: u32,
 }
-#[derive(Debug, Component)]
+
+// status effects currently on an enemy
+#[derive(Debug, Component, Clone, Default, Serialize, Deserialize)]
+pub struct StatusEffects(pub Vec<ActiveEffect>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveEffect {
+    // name in `GameConfig::status_effects`
+    pub name: String,
+    pub stacks: u32,
+    // seconds until the effect wears off
+    pub remaining: f32,
+    // seconds until the next damage tick
+    pub next_tick: f32,
+}
+
+// name of the enemy kind in `EnemyConfig::enemy_types`
+#[derive(Debug, Component, Clone, Serialize, Deserialize)]
+pub struct EnemyType(pub String);
+
+#[derive(Debug, Component, Clone, Serialize, Deserialize)]
 pub struct Moves {
-    pub path_index: (usize, usize),
+    // tile the enemy is leaving and the one it is heading to
+    pub from: Hex,
+    pub to: Hex,
     pub lerp: f32,
     pub speed: f32,
 }
 
 // Towers
-#[derive(Debug, Component)]
+#[derive(Debug, Component, Clone, Serialize, Deserialize)]
 pub struct Tower {
     pub tower_type: TowerType,
+    // spent on the tower including upgrades
     pub cost: u32,
+    // upgrade level, 0 until upgraded
+    pub level: u32,
 }
 
-#[derive(
-    Debug, Default, PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord, Serialize, Deserialize,
-)]
-pub enum TowerType {
+// name of the tower kind in `TowersConfig::tower_types`
+#[derive(Debug, PartialEq, Eq, Hash, Clone, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TowerType(pub String);
+
+// which enemy in range a tower shoots at
+#[derive(Debug, Component, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TargetingMode {
+    // furthest along its path
     #[default]
-    Small,
-    Medium,
-    Large,
+    First,
+    Last,
+    // most health left
+    Strongest,
+    Weakest,
+    Closest,
 }
 
-impl TowerType {
-    pub fn range(&self) -> u32 {
-        match self {
-            TowerType::Small => 1,
-            TowerType::Medium => 2,
-            TowerType::Large => 3,
-        }
-    }
-    pub fn damage(&self) -> u32 {
-        match self {
-            TowerType::Small => 1,
-            TowerType::Medium => 2,
-            TowerType::Large => 3,
-        }
-    }
+impl TargetingMode {
+    pub const ALL: [TargetingMode; 5] = [
+        TargetingMode::First,
+        TargetingMode::Last,
+        TargetingMode::Strongest,
+        TargetingMode::Weakest,
+        TargetingMode::Closest,
+    ];
+}
+
+// flies towards its target and damages it on hit
+#[derive(Debug, Component)]
+pub struct Projectile {
+    pub target: Entity,
+    pub damage: u32,
+    pub damage_type: String,
+    // status effect put on the target on hit
+    pub effect: Option<String>,
+    // world units per second
+    pub speed: f32,
 }
+
+// time until the tower fires again
+#[derive(Debug, Component)]
+pub struct Reload(pub Timer);
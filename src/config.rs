@@ -4,33 +4,61 @@ use hexx::Vec2;
 use serde::{Deserialize, Serialize};
 use tracing::{event, Level};
 
-use crate::components::TowerType;
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GameConfig {
     pub map_radius: u32,
+    // map file to load instead of generating a map
+    pub map: Option<String>,
+    pub map_generation: MapGenerationConfig,
     pub hex_size: Vec2,
     pub seed: Option<u64>,
     pub zoom_speed: f32,
     pub starting_budget: u32,
     pub tower_config: TowersConfig,
     pub enemy_config: EnemyConfig,
+    // damage multiplier by damage type and then armor class, 1 when missing
+    pub damage_effectiveness: BTreeMap<String, BTreeMap<String, f32>>,
+    // timed effects towers put on the enemies they hit, by name
+    pub status_effects: BTreeMap<String, StatusEffectConfig>,
+    // scripted waves, enemies spawn endlessly at `enemy_spawn_rate` when empty
+    pub waves: Vec<WaveConfig>,
+    // how the game is won or lost
+    pub outcome: OutcomeConfig,
+    // seconds until the game timer runs out
     pub game_length: f32,
     pub game_over_timer_length: f32,
+    pub tick_length: f32,
+    // ticks paths are calculated in the background for before they are used, the
+    // simulation waits for them if they are not ready by then, 0 calculates them at once
+    pub path_calculation_ticks: u32,
+    // localhost port to accept command clients on, overridden by `--port`
+    pub command_server_port: Option<u16>,
+    // file the save and load buttons of the ui use
+    pub save_path: String,
 }
 
 impl Default for GameConfig {
     fn default() -> Self {
         Self {
             map_radius: 20,
+            map: None,
+            map_generation: MapGenerationConfig::default(),
             hex_size: Vec2::splat(10.),
             seed: None,
             zoom_speed: 1.,
             starting_budget: 50,
             tower_config: TowersConfig::default(),
             enemy_config: EnemyConfig::default(),
+            damage_effectiveness: default_damage_effectiveness(),
+            status_effects: default_status_effects(),
+            waves: Vec::new(),
+            outcome: OutcomeConfig::default(),
             game_length: 60.,
             game_over_timer_length: 5.,
+            tick_length: 1. / 60.,
+            path_calculation_ticks: 6,
+            command_server_port: None,
+            save_path: "save.yaml".to_string(),
         }
     }
 }
@@ -38,14 +66,38 @@ impl Default for GameConfig {
 impl GameConfig {
     pub fn load(path: &str) -> Self {
         let config_yaml: String = std::fs::read_to_string(path).unwrap();
-        if let Ok(config) = serde_yaml::from_str(&config_yaml) {
-            config
+        if let Ok(config) = serde_yaml::from_str::<GameConfig>(&config_yaml) {
+            config.validated()
         } else {
             event!(Level::WARN, "Unable to load config from file");
             GameConfig::default()
         }
     }
 
+    // fixes values that the game can not be played with, warning about each
+    pub fn validated(mut self) -> Self {
+        for (name, enemy_type) in self.enemy_config.enemy_types.iter_mut() {
+            let (min_health, max_health) = enemy_type.min_max_health;
+            if min_health > max_health {
+                event!(
+                    Level::WARN,
+                    "Minimum health of {name} is above its maximum, swapping them"
+                );
+                enemy_type.min_max_health = (max_health, min_health);
+            }
+        }
+        self
+    }
+
+    // how well a damage type works against an armor class
+    pub fn effectiveness(&self, damage_type: &str, armor_class: &str) -> f32 {
+        self.damage_effectiveness
+            .get(damage_type)
+            .and_then(|classes| classes.get(armor_class))
+            .copied()
+            .unwrap_or(1.)
+    }
+
     pub fn export(&self, path: &str) -> Result<(), &'static str> {
         let config_as_str =
             serde_yaml::to_string(&self).map_err(|_| "Unable to serialize config")?;
@@ -62,62 +114,562 @@ impl GameConfig {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum MapGenerator {
+    // independent random tiles
+    Random,
+    // mountain ridges and open valleys
+    Noise,
+    // smoothed caves and ridges
+    CellularAutomata,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct MapGenerationConfig {
+    pub generator: MapGenerator,
+    // share of tiles that are mountains
+    pub mountain_ratio: f32,
+    // size of noise features in tiles
+    pub noise_scale: f32,
+    // cellular automata iterations
+    pub smoothing_passes: u32,
+    // clears a mountain free way from every spawn to the goal,
+    // enemies never come out of spawns cut off by mountains otherwise
+    pub ensure_connectivity: bool,
+}
+
+impl Default for MapGenerationConfig {
+    fn default() -> Self {
+        Self {
+            generator: MapGenerator::Random,
+            mountain_ratio: 0.25,
+            noise_scale: 8.,
+            smoothing_passes: 4,
+            ensure_connectivity: true,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TowersConfig {
-    pub tower_type: BTreeMap<TowerType, TowerConfig>,
+    // tower kinds by name
+    pub tower_types: BTreeMap<String, TowerConfig>,
+    // seconds between two hits of the area towers on enemies on their damaging tiles
     pub damaging_rate: f32,
     pub tower_damage_alpha: f32,
+    // share of the money spent on a tower returned when selling it
+    pub refund_ratio: f32,
+}
+
+impl TowersConfig {
+    // the kind of tower called `name` or `short_name`, with its full name
+    pub fn find(&self, name: &str) -> Option<(&String, &TowerConfig)> {
+        self.tower_types.get_key_value(name).or_else(|| {
+            self.tower_types
+                .iter()
+                .find(|(_, t)| t.short_name.as_deref() == Some(name))
+        })
+    }
+
+    // names of the tower kinds, to list in errors
+    pub fn kinds(&self) -> Vec<String> {
+        self.tower_types.keys().cloned().collect()
+    }
 }
 
 impl Default for TowersConfig {
     fn default() -> Self {
         let small = TowerConfig {
+            short_name: Some("s".to_string()),
+            range: 1,
+            damage: 1,
+            damage_type: "physical".to_string(),
+            fire_rate: 2.,
             cost: 8,
+            upgrades: default_upgrades(8),
             scale: 0.8,
+            color: "#ffa500".to_string(),
+            projectile: None,
+            effect: None,
         };
         let medium = TowerConfig {
+            short_name: Some("m".to_string()),
+            range: 2,
+            damage: 2,
+            damage_type: "physical".to_string(),
+            fire_rate: 2.,
             cost: 20,
+            upgrades: default_upgrades(20),
             scale: 1.,
+            color: "#ff4500".to_string(),
+            projectile: None,
+            effect: None,
         };
         let large = TowerConfig {
+            short_name: Some("l".to_string()),
+            range: 3,
+            damage: 3,
+            damage_type: "physical".to_string(),
+            fire_rate: 2.,
             cost: 50,
+            upgrades: default_upgrades(50),
             scale: 1.5,
+            color: "#dc143c".to_string(),
+            projectile: None,
+            effect: None,
+        };
+        let archer = TowerConfig {
+            short_name: None,
+            range: 4,
+            damage: 4,
+            damage_type: "physical".to_string(),
+            fire_rate: 1.,
+            cost: 25,
+            upgrades: default_upgrades(25),
+            scale: 0.9,
+            color: "#1e90ff".to_string(),
+            projectile: Some(ProjectileConfig { speed: 8. }),
+            effect: None,
+        };
+        let frost = TowerConfig {
+            short_name: None,
+            range: 2,
+            damage: 0,
+            damage_type: "cold".to_string(),
+            fire_rate: 1.,
+            cost: 15,
+            upgrades: default_upgrades(15),
+            scale: 0.9,
+            color: "#87ceeb".to_string(),
+            projectile: None,
+            effect: Some("slow".to_string()),
+        };
+        let freezer = TowerConfig {
+            short_name: None,
+            range: 3,
+            damage: 1,
+            damage_type: "cold".to_string(),
+            fire_rate: 0.25,
+            cost: 30,
+            upgrades: default_upgrades(30),
+            scale: 1.,
+            color: "#e0ffff".to_string(),
+            projectile: Some(ProjectileConfig { speed: 6. }),
+            effect: Some("freeze".to_string()),
+        };
+        let poison = TowerConfig {
+            short_name: None,
+            range: 3,
+            damage: 1,
+            damage_type: "poison".to_string(),
+            fire_rate: 1.,
+            cost: 25,
+            upgrades: default_upgrades(25),
+            scale: 0.9,
+            color: "#7fff00".to_string(),
+            projectile: Some(ProjectileConfig { speed: 8. }),
+            effect: Some("poison".to_string()),
+        };
+        let mage = TowerConfig {
+            short_name: None,
+            range: 3,
+            damage: 3,
+            damage_type: "magic".to_string(),
+            fire_rate: 0.75,
+            cost: 35,
+            upgrades: default_upgrades(35),
+            scale: 1.,
+            color: "#9400d3".to_string(),
+            projectile: Some(ProjectileConfig { speed: 10. }),
+            effect: None,
+        };
+        let flame = TowerConfig {
+            short_name: None,
+            range: 1,
+            damage: 1,
+            damage_type: "fire".to_string(),
+            fire_rate: 2.,
+            cost: 30,
+            upgrades: default_upgrades(30),
+            scale: 1.1,
+            color: "#ff8c00".to_string(),
+            projectile: None,
+            effect: Some("burn".to_string()),
         };
         Self {
-            tower_type: BTreeMap::from([
-                (TowerType::Small, small),
-                (TowerType::Medium, medium),
-                (TowerType::Large, large),
+            tower_types: BTreeMap::from([
+                ("Small".to_string(), small),
+                ("Medium".to_string(), medium),
+                ("Large".to_string(), large),
+                ("Archer".to_string(), archer),
+                ("Frost".to_string(), frost),
+                ("Freezer".to_string(), freezer),
+                ("Poison".to_string(), poison),
+                ("Flame".to_string(), flame),
+                ("Mage".to_string(), mage),
             ]),
             damaging_rate: 0.3,
             tower_damage_alpha: 0.7,
+            refund_ratio: 0.5,
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TowerConfig {
+    // accepted by the tower command in place of the name, e.g. `s` for Small
+    pub short_name: Option<String>,
+    // in tiles
+    pub range: u32,
+    // dealt on every damaging tick to enemies on the path tiles in range,
+    // or to the target only on each shot of projectile towers
+    pub damage: u32,
+    pub damage_type: String,
+    // shots per second of projectile towers
+    pub fire_rate: f32,
+    pub cost: u32,
+    // levels a placed tower can be upgraded through, in order
+    pub upgrades: Vec<TowerUpgrade>,
+    pub scale: f32,
+    // hex color code
+    pub color: String,
+    // fires projectiles at a single target instead of hitting the whole area
+    pub projectile: Option<ProjectileConfig>,
+    // name of the status effect put on enemies hit
+    pub effect: Option<String>,
+}
+
+impl TowerConfig {
+    // stats of a tower of this kind upgraded to `level`, 0 being the base level
+    pub fn stats(&self, level: u32) -> TowerStats {
+        let base = TowerStats {
+            range: self.range,
+            damage: self.damage,
+            fire_rate: self.fire_rate,
+            scale: self.scale,
+        };
+        let Some(upgrade) = level
+            .checked_sub(1)
+            .and_then(|i| self.upgrades.get(i as usize))
+        else {
+            return base;
+        };
+        TowerStats {
+            range: (base.range as f32 * upgrade.range).round() as u32,
+            damage: (base.damage as f32 * upgrade.damage).round() as u32,
+            fire_rate: base.fire_rate * upgrade.fire_rate,
+            scale: base.scale * upgrade.scale,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TowerStats {
+    pub range: u32,
+    pub damage: u32,
+    pub fire_rate: f32,
+    pub scale: f32,
+}
+
+// multipliers are relative to the base stats of the tower kind
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct TowerUpgrade {
     pub cost: u32,
+    pub range: f32,
+    pub damage: f32,
+    pub fire_rate: f32,
     pub scale: f32,
 }
 
+fn default_upgrades(base_cost: u32) -> Vec<TowerUpgrade> {
+    vec![
+        TowerUpgrade {
+            cost: base_cost,
+            range: 1.,
+            damage: 2.,
+            fire_rate: 1.25,
+            scale: 1.15,
+        },
+        TowerUpgrade {
+            cost: base_cost * 2,
+            range: 1.5,
+            damage: 3.,
+            fire_rate: 1.5,
+            scale: 1.3,
+        },
+    ]
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct ProjectileConfig {
+    // tiles per second
+    pub speed: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StatusEffectConfig {
+    // seconds
+    pub duration: f32,
+    // scales enemy speed per stack, 0 stuns
+    pub speed_multiplier: f32,
+    // dealt per stack every `tick_interval` seconds, ignoring armor
+    pub damage_per_tick: u32,
+    pub damage_type: String,
+    pub tick_interval: f32,
+    // what hitting an enemy that already has the effect does
+    pub stacking: Stacking,
+    pub max_stacks: u32,
+    // hex color code affected enemies are tinted with
+    pub color: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Stacking {
+    // restart the duration
+    Refresh,
+    // add the duration to what is left
+    Extend,
+    // add a stack up to `max_stacks` and restart the duration
+    Stack,
+}
+
+fn default_damage_effectiveness() -> BTreeMap<String, BTreeMap<String, f32>> {
+    let row = |multipliers: &[(&str, f32)]| {
+        multipliers
+            .iter()
+            .map(|(armor_class, multiplier)| (armor_class.to_string(), *multiplier))
+            .collect()
+    };
+    BTreeMap::from([
+        (
+            "physical".to_string(),
+            row(&[("unarmored", 1.25), ("heavy", 0.5), ("fortified", 0.35)]),
+        ),
+        (
+            "magic".to_string(),
+            row(&[("light", 0.75), ("heavy", 1.5), ("fortified", 1.25)]),
+        ),
+        (
+            "fire".to_string(),
+            row(&[("light", 1.25), ("unarmored", 1.5), ("heavy", 0.75)]),
+        ),
+        (
+            "poison".to_string(),
+            row(&[("unarmored", 1.25), ("fortified", 0.5)]),
+        ),
+    ])
+}
+
+fn default_status_effects() -> BTreeMap<String, StatusEffectConfig> {
+    let slow = StatusEffectConfig {
+        duration: 2.,
+        speed_multiplier: 0.5,
+        damage_per_tick: 0,
+        damage_type: "cold".to_string(),
+        tick_interval: 1.,
+        stacking: Stacking::Refresh,
+        max_stacks: 1,
+        color: "#87ceeb".to_string(),
+    };
+    let freeze = StatusEffectConfig {
+        duration: 1.,
+        speed_multiplier: 0.,
+        damage_per_tick: 0,
+        damage_type: "cold".to_string(),
+        tick_interval: 1.,
+        stacking: Stacking::Refresh,
+        max_stacks: 1,
+        color: "#e0ffff".to_string(),
+    };
+    let poison = StatusEffectConfig {
+        duration: 4.,
+        speed_multiplier: 1.,
+        damage_per_tick: 1,
+        damage_type: "poison".to_string(),
+        tick_interval: 0.5,
+        stacking: Stacking::Stack,
+        max_stacks: 5,
+        color: "#7fff00".to_string(),
+    };
+    let burn = StatusEffectConfig {
+        duration: 2.,
+        speed_multiplier: 1.,
+        damage_per_tick: 2,
+        damage_type: "fire".to_string(),
+        tick_interval: 0.25,
+        stacking: Stacking::Extend,
+        max_stacks: 1,
+        color: "#ff8c00".to_string(),
+    };
+    BTreeMap::from([
+        ("slow".to_string(), slow),
+        ("freeze".to_string(), freeze),
+        ("poison".to_string(), poison),
+        ("burn".to_string(), burn),
+    ])
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct EnemyConfig {
     pub enemy_spawn_rate: f32,
     pub base_speed: f32,
-    pub min_max_health: (u32, u32),
+    // enemy kinds by name
+    pub enemy_types: BTreeMap<String, EnemyTypeConfig>,
 }
 
 impl Default for EnemyConfig {
     fn default() -> Self {
+        let runner = EnemyTypeConfig {
+            min_max_health: (3, 6),
+            speed: 2.,
+            reward: 3,
+            goal_damage: 1,
+            armor: 0,
+            armor_class: "light".to_string(),
+            regeneration: 0,
+            size: 0.6,
+            color: "#ffd700".to_string(),
+            spawn_weight: 3,
+        };
+        let tank = EnemyTypeConfig {
+            min_max_health: (20, 30),
+            speed: 0.5,
+            reward: 8,
+            goal_damage: 2,
+            armor: 2,
+            armor_class: "heavy".to_string(),
+            regeneration: 0,
+            size: 1.3,
+            color: "#4b4b4b".to_string(),
+            spawn_weight: 1,
+        };
+        let swarm = EnemyTypeConfig {
+            min_max_health: (1, 3),
+            speed: 1.2,
+            reward: 1,
+            goal_damage: 1,
+            armor: 0,
+            armor_class: "light".to_string(),
+            regeneration: 0,
+            size: 0.4,
+            color: "#8b008b".to_string(),
+            spawn_weight: 4,
+        };
+        let regenerating = EnemyTypeConfig {
+            min_max_health: (10, 15),
+            speed: 0.8,
+            reward: 6,
+            goal_damage: 1,
+            armor: 0,
+            armor_class: "unarmored".to_string(),
+            regeneration: 1,
+            size: 1.,
+            color: "#00ced1".to_string(),
+            spawn_weight: 2,
+        };
+        let boss = EnemyTypeConfig {
+            min_max_health: (150, 200),
+            speed: 0.4,
+            reward: 50,
+            goal_damage: 10,
+            armor: 3,
+            armor_class: "fortified".to_string(),
+            regeneration: 2,
+            size: 2.,
+            color: "#000000".to_string(),
+            spawn_weight: 0,
+        };
         Self {
             base_speed: 2.,
             enemy_spawn_rate: 0.2,
-            min_max_health: (5, 20),
+            enemy_types: BTreeMap::from([
+                ("runner".to_string(), runner),
+                ("tank".to_string(), tank),
+                ("swarm".to_string(), swarm),
+                ("regenerating".to_string(), regenerating),
+                ("boss".to_string(), boss),
+            ]),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutcomeConfig {
+    // lives of the goal, the game is lost as soon as they run out
+    pub lives: u32,
+    // win once every wave is spawned and no enemy is left, needs waves
+    pub win_after_waves: bool,
+    // win when the game timer runs out, the timer is ignored otherwise
+    pub win_on_timer: bool,
+}
+
+impl Default for OutcomeConfig {
+    fn default() -> Self {
+        Self {
+            lives: 20,
+            win_after_waves: true,
+            win_on_timer: true,
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EnemyTypeConfig {
+    pub min_max_health: (u32, u32),
+    // multiplier of `base_speed`
+    pub speed: f32,
+    // budget earned for killing it
+    pub reward: u32,
+    // lives taken off the goal and enemy score added when it reaches the goal
+    pub goal_damage: u32,
+    // taken off every hit, a hit always deals at least 1 damage
+    pub armor: u32,
+    // row of `damage_effectiveness` hits on this enemy use
+    pub armor_class: String,
+    // health regained on every tower damage tick
+    pub regeneration: u32,
+    pub size: f32,
+    // hex color code
+    pub color: String,
+    // how often endless spawning picks this type relative to the others
+    pub spawn_weight: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WaveConfig {
+    // spawned one after the other, in order
+    pub enemies: Vec<WaveEnemies>,
+    // seconds between two enemies of the wave
+    pub spawn_interval: f32,
+    // seconds between the last enemy of this wave and the next wave
+    pub next_wave_delay: f32,
+    // indices of the spawns enemies come from, all spawns when empty,
+    // an enemy waits while none of them has a path
+    pub spawns: Vec<usize>,
+}
+
+impl WaveConfig {
+    pub fn enemy_count(&self) -> u32 {
+        self.enemies.iter().map(|enemies| enemies.count).sum()
+    }
+
+    // the group the nth enemy of the wave belongs to
+    pub fn enemies_at(&self, n: u32) -> Option<&WaveEnemies> {
+        let mut first = 0;
+        self.enemies.iter().find(|enemies| {
+            first += enemies.count;
+            n < first
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WaveEnemies {
+    pub count: u32,
+    // name of one of the `enemy_types`
+    pub enemy_type: String,
+}
+
 #[cfg(test)]
 mod tests {
     use crate::config::GameConfig;
@@ -127,4 +679,23 @@ mod tests {
         let config = GameConfig::default();
         println!("{}", serde_yaml::to_string(&config).unwrap());
     }
+
+    #[test]
+    fn deserialize_config_file() {
+        let config_yaml = std::fs::read_to_string(crate::CONFIG_PATH).unwrap();
+        let config: GameConfig = serde_yaml::from_str(&config_yaml).unwrap();
+        assert!(!config.waves.is_empty());
+    }
+
+    #[test]
+    fn health_ranges_are_validated() {
+        let mut config = GameConfig::default();
+        let runner = config.enemy_config.enemy_types.get_mut("runner").unwrap();
+        runner.min_max_health = (6, 3);
+        let config = config.validated();
+        assert_eq!(
+            config.enemy_config.enemy_types["runner"].min_max_health,
+            (3, 6)
+        );
+    }
 }
@@ -0,0 +1,372 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use crossbeam_channel::Sender;
+use hexx::Hex;
+
+use crate::communication::TDCommand;
+use crate::components::*;
+use crate::config::GameConfig;
+use crate::resources::SimulationTick;
+use crate::{setup_tower_defense_headless, AppState};
+
+// What an `Env` is set up with
+#[derive(Debug, Clone)]
+pub struct EnvConfig {
+    pub game: GameConfig,
+    // simulation ticks run by every step
+    pub ticks_per_step: u64,
+    pub reward: RewardConfig,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            game: GameConfig::default(),
+            ticks_per_step: 10,
+            reward: RewardConfig::default(),
+        }
+    }
+}
+
+// The reward of a step is the change of the score board over the step, weighted
+#[derive(Debug, Clone, Copy)]
+pub struct RewardConfig {
+    // per enemy killed
+    pub player_score: f32,
+    // per enemy reaching the goal
+    pub enemy_score: f32,
+}
+
+impl Default for RewardConfig {
+    fn default() -> Self {
+        Self {
+            player_score: 1.0,
+            enemy_score: -1.0,
+        }
+    }
+}
+
+// Planes of the board in an `Observation`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    // 1 for the tiles of the board, 0 for the corners of the grid outside of it
+    OnBoard,
+    Mountain,
+    // level of the tower plus one, 0 without a tower
+    Tower,
+    Goal,
+    Spawn,
+    OnPath,
+    // damage dealt to enemies on the tile
+    Damaging,
+    // enemies on the tile and their health left
+    Enemies,
+    EnemyHealth,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 9] = [
+        Channel::OnBoard,
+        Channel::Mountain,
+        Channel::Tower,
+        Channel::Goal,
+        Channel::Spawn,
+        Channel::OnPath,
+        Channel::Damaging,
+        Channel::Enemies,
+        Channel::EnemyHealth,
+    ];
+}
+
+// The game as seen by an agent after a step
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub tick: u64,
+    pub budget: u32,
+    pub player_score: u32,
+    pub enemy_score: u32,
+    pub lives: u32,
+    // the board is laid out on a grid of axial coordinates
+    // from `-radius` to `radius` in both directions
+    pub radius: u32,
+    // a plane of the grid for every channel in `Channel::ALL`, row by row
+    pub grid: Vec<f32>,
+}
+
+impl Observation {
+    // tiles per row and rows per plane of the grid
+    pub fn width(&self) -> usize {
+        2 * self.radius as usize + 1
+    }
+
+    // shape of the grid as channels, rows and columns
+    pub fn shape(&self) -> [usize; 3] {
+        [Channel::ALL.len(), self.width(), self.width()]
+    }
+
+    fn index(&self, channel: Channel, hex: Hex) -> Option<usize> {
+        let radius = self.radius as i32;
+        if hex.x.abs() > radius || hex.y.abs() > radius {
+            return None;
+        }
+        let (column, row) = ((hex.x + radius) as usize, (hex.y + radius) as usize);
+        Some((channel as usize * self.width() + row) * self.width() + column)
+    }
+
+    // the plane of a channel
+    pub fn channel(&self, channel: Channel) -> &[f32] {
+        let plane = self.width().pow(2);
+        &self.grid[channel as usize * plane..(channel as usize + 1) * plane]
+    }
+
+    // value of a channel on a tile, 0 off the grid
+    pub fn get(&self, channel: Channel, hex: Hex) -> f32 {
+        self.index(channel, hex)
+            .map_or(0.0, |index| self.grid[index])
+    }
+}
+
+// A headless game stepped by an agent, each step sends the actions
+// to the game and runs `EnvConfig::ticks_per_step` simulation ticks
+pub struct Env {
+    config: EnvConfig,
+    app: App,
+    tx: Sender<TDCommand>,
+    // score board at the end of the last step
+    scores: (u32, u32),
+}
+
+impl Env {
+    pub fn new(config: EnvConfig) -> Self {
+        let seed = config.game.seed.unwrap_or_default();
+        let (app, tx) = start_game(&config.game, seed);
+        let mut env = Self {
+            config,
+            app,
+            tx,
+            scores: (0, 0),
+        };
+        env.set_up();
+        env
+    }
+
+    // starts a new game from `seed`, the same seed always starts the same game
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        (self.app, self.tx) = start_game(&self.config.game, seed);
+        self.set_up();
+        self.observe()
+    }
+
+    // sends the actions and runs the ticks of a step, returns what the game looks like
+    // after it, the reward for it and whether the game is over
+    pub fn step(&mut self, actions: &[TDCommand]) -> (Observation, f32, bool) {
+        if !self.done() {
+            for action in actions {
+                // the receiver lives as long as the app
+                let _ = self.tx.send(action.clone());
+            }
+            self.run_ticks(self.config.ticks_per_step);
+        }
+        let scores = self.score_board();
+        // scores go down when an older game is loaded
+        let delta = |now: u32, before: u32| now as f32 - before as f32;
+        let reward = self.config.reward.player_score * delta(scores.0, self.scores.0)
+            + self.config.reward.enemy_score * delta(scores.1, self.scores.1);
+        self.scores = scores;
+        (self.observe(), reward, self.done())
+    }
+
+    // whether the game is over and has to be reset to go on
+    pub fn done(&self) -> bool {
+        self.app.world.resource::<State<AppState>>().0 == AppState::GameOver
+    }
+
+    pub fn observe(&mut self) -> Observation {
+        let world = &mut self.app.world;
+        let lives = world
+            .query_filtered::<&Lives, With<IsGoal>>()
+            .iter(world)
+            .map(|lives| lives.0)
+            .sum();
+        let (budget, score_board, grid) = world
+            .query::<(&Budget, &ScoreBoard, &HexGrid)>()
+            .single(world);
+        let radius = grid.entities.keys().map(|hex| hex.ulength()).max();
+        let mut observation = Observation {
+            tick: world.resource::<SimulationTick>().0,
+            budget: budget.0,
+            player_score: score_board.player_score,
+            enemy_score: score_board.enemy_score,
+            lives,
+            radius: radius.unwrap_or_default(),
+            grid: Vec::new(),
+        };
+        observation.grid = vec![0.0; Channel::ALL.len() * observation.width().pow(2)];
+        let mut set = |channel: Channel, hex: Hex, value: f32| {
+            if let Some(index) = observation.index(channel, hex) {
+                observation.grid[index] += value;
+            }
+        };
+
+        let mut tiles = world.query::<(
+            &Coords,
+            &Tile,
+            Option<&IsGoal>,
+            Option<&IsSpawn>,
+            Option<&OnPath>,
+            Option<&Damaging>,
+        )>();
+        for (coords, tile, is_goal, is_spawn, on_path, damaging) in tiles.iter(world) {
+            let hex = coords.0;
+            set(Channel::OnBoard, hex, 1.0);
+            if tile.tile_type == TileType::Mountain {
+                set(Channel::Mountain, hex, 1.0);
+            }
+            if is_goal.is_some() {
+                set(Channel::Goal, hex, 1.0);
+            }
+            if is_spawn.is_some() {
+                set(Channel::Spawn, hex, 1.0);
+            }
+            if on_path.is_some() {
+                set(Channel::OnPath, hex, 1.0);
+            }
+            if let Some(damaging) = damaging {
+                set(Channel::Damaging, hex, damaging.value as f32);
+            }
+        }
+        for (coords, tower) in world.query::<(&Coords, &Tower)>().iter(world) {
+            set(Channel::Tower, coords.0, tower.level as f32 + 1.0);
+        }
+        // enemies count on the tile they are closer to
+        for (enemy, moves) in world.query::<(&Enemy, &Moves)>().iter(world) {
+            let hex = if moves.lerp < 0.5 {
+                moves.from
+            } else {
+                moves.to
+            };
+            set(Channel::Enemies, hex, 1.0);
+            set(Channel::EnemyHealth, hex, enemy.health as f32);
+        }
+        observation
+    }
+
+    // runs the first tick, which sets the board up
+    fn set_up(&mut self) {
+        self.run_ticks(1);
+        self.scores = self.score_board();
+    }
+
+    fn score_board(&mut self) -> (u32, u32) {
+        let world = &mut self.app.world;
+        let score_board = world.query::<&ScoreBoard>().single(world);
+        (score_board.player_score, score_board.enemy_score)
+    }
+
+    // updates the app a tick at a time, until `ticks` ran or the game is over
+    fn run_ticks(&mut self, ticks: u64) {
+        let tick_length: Duration = self.app.world.resource::<FixedTime>().period;
+        let target = self.app.world.resource::<SimulationTick>().0 + ticks;
+        while self.app.world.resource::<SimulationTick>().0 < target && !self.done() {
+            let mut strategy = self.app.world.resource_mut::<TimeUpdateStrategy>();
+            if let TimeUpdateStrategy::ManualInstant(instant) = strategy.as_mut() {
+                *instant += tick_length;
+            }
+            self.app.update();
+        }
+    }
+}
+
+// a headless app for `game` started from `seed`, stepped by hand
+fn start_game(game: &GameConfig, seed: u64) -> (App, Sender<TDCommand>) {
+    let game = GameConfig {
+        seed: Some(seed),
+        ..game.clone()
+    };
+    let (mut app, tx) = setup_tower_defense_headless(game);
+    app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+    (app, tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_command;
+
+    #[test]
+    fn env_steps_the_game() {
+        let config = EnvConfig {
+            game: GameConfig {
+                game_length: 3.,
+                ..default()
+            },
+            ..default()
+        };
+        // the game is won when its timer runs out, lives last far longer
+        let game_over_tick = (config.game.game_length / config.game.tick_length).round() as u64;
+        let ticks_per_step = config.ticks_per_step;
+        let mut env = Env::new(config.clone());
+        let mut other = Env::new(config);
+        let observation = env.reset(7);
+        assert_eq!(observation, other.reset(7));
+        assert_eq!(observation.tick, 1);
+        let start = observation.tick;
+        assert_eq!(
+            observation.grid.len(),
+            observation.shape().iter().product::<usize>()
+        );
+        let goal = Hex::ZERO;
+        assert_eq!(observation.get(Channel::Goal, goal), 1.0);
+        assert_eq!(observation.get(Channel::OnBoard, Hex::new(100, 0)), 0.0);
+        assert_eq!(
+            observation.get(Channel::OnBoard, Hex::new(observation.radius as i32, 0)),
+            1.0
+        );
+
+        // a tower next to the goal, as the action of the first step
+        let tower = goal
+            .all_neighbors()
+            .into_iter()
+            .find(|hex| {
+                observation.get(Channel::Mountain, *hex) == 0.0
+                    && observation.get(Channel::Spawn, *hex) == 0.0
+            })
+            .unwrap();
+        let actions = [parse_command(&format!("tower {},{} Small", tower.x, tower.y)).unwrap()];
+        let mut total = 0.0;
+        let mut steps = 0;
+        loop {
+            let actions: &[TDCommand] = if steps == 0 { &actions } else { &[] };
+            let (observation, reward, done) = env.step(actions);
+            assert_eq!((observation.clone(), reward, done), other.step(actions));
+            steps += 1;
+            total += reward;
+            assert_eq!(observation.get(Channel::Tower, tower), 1.0);
+            assert_eq!(
+                total,
+                observation.player_score as f32 - observation.enemy_score as f32
+            );
+            let enemies: f32 = observation.channel(Channel::Enemies).iter().sum();
+            let world = &mut env.app.world;
+            assert_eq!(
+                enemies as usize,
+                world.query::<&Enemy>().iter(world).count()
+            );
+            if done {
+                break;
+            }
+        }
+        // the last step stops on the tick the game is over, stepping on does nothing
+        assert_eq!(
+            steps,
+            (game_over_tick - start + ticks_per_step - 1) / ticks_per_step
+        );
+        let (observation, reward, done) = env.step(&[]);
+        assert_eq!(
+            (observation.tick, reward, done),
+            (game_over_tick, 0.0, true)
+        );
+    }
+}
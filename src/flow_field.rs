@@ -0,0 +1,222 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use hexx::Hex;
+use serde::{Deserialize, Serialize};
+
+use crate::components::TileType;
+
+// cost of stepping onto a tile, none for towers and mountains which block the way
+pub fn step_cost(tile_type: TileType, has_tower: bool) -> Option<u32> {
+    (tile_type == TileType::Plains && !has_tower).then_some(1)
+}
+
+// Cheapest way to the goal from every tile of the board, computed once with a
+// reverse Dijkstra from the goal and shared by all enemies
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "FlowFieldTiles", into = "FlowFieldTiles")]
+pub struct FlowField {
+    pub goal: Hex,
+    // cost of the cheapest way from a tile to the goal
+    costs: HashMap<Hex, u32>,
+    // next tile on that way
+    next: HashMap<Hex, Hex>,
+}
+
+impl FlowField {
+    // `cost` gives the cost of stepping onto a tile, none for tiles off the board
+    pub fn new(goal: Hex, cost: impl Fn(Hex) -> Option<u32>) -> Self {
+        let mut costs = HashMap::from([(goal, 0)]);
+        let mut next = HashMap::new();
+        if cost(goal).is_none() {
+            return Self { goal, costs, next };
+        }
+        // ties are broken by coordinates so the field does not depend on hashing
+        let mut open = BinaryHeap::from([Reverse((0, goal.x, goal.y))]);
+        while let Some(Reverse((hex_cost, x, y))) = open.pop() {
+            let hex = Hex::new(x, y);
+            if costs.get(&hex).is_some_and(|c| *c < hex_cost) {
+                continue;
+            }
+            let step = cost(hex).unwrap_or_default();
+            for neighbor in hex.all_neighbors() {
+                if cost(neighbor).is_none() {
+                    continue;
+                }
+                let neighbor_cost = hex_cost + step;
+                if costs.get(&neighbor).map_or(true, |c| neighbor_cost < *c) {
+                    costs.insert(neighbor, neighbor_cost);
+                    next.insert(neighbor, hex);
+                    open.push(Reverse((neighbor_cost, neighbor.x, neighbor.y)));
+                }
+            }
+        }
+        Self { goal, costs, next }
+    }
+
+    // cost of the cheapest way from `hex` to the goal, none if there is none
+    pub fn cost(&self, hex: Hex) -> Option<u32> {
+        self.costs.get(&hex).copied()
+    }
+
+    // where an enemy on `hex` goes next, an enemy on a tile that got blocked
+    // steps to the open neighbour closest to the goal, none on the goal
+    pub fn next_step(&self, hex: Hex) -> Option<Hex> {
+        if hex == self.goal {
+            return None;
+        }
+        self.next.get(&hex).copied().or_else(|| {
+            hex.all_neighbors()
+                .into_iter()
+                .filter_map(|neighbor| Some((self.cost(neighbor)?, neighbor)))
+                .min_by_key(|(cost, _)| *cost)
+                .map(|(_, neighbor)| neighbor)
+        })
+    }
+
+    // whether every one of `hexes` has a way to the goal
+    pub fn connects(&self, hexes: &[Hex]) -> bool {
+        hexes.iter().all(|hex| self.costs.contains_key(hex))
+    }
+
+    // tiles from `start` to the goal, both included
+    pub fn path(&self, start: Hex) -> Option<Vec<Hex>> {
+        self.cost(start)?;
+        let path: Vec<Hex> =
+            std::iter::successors(Some(start), |hex| self.next_step(*hex)).collect();
+        Some(path)
+    }
+}
+
+// whether blocking `hex` may cut open tiles off from each other, which needs the
+// open tiles around it to be split into more than one run, a single run leads
+// around it so the full flow field only has to be checked for the others
+pub fn may_disconnect(hex: Hex, is_open: impl Fn(Hex) -> bool) -> bool {
+    // the neighbours go around the tile in order
+    let open = hex.all_neighbors().map(is_open);
+    let runs = (0..6).filter(|i| open[*i] && !open[(i + 1) % 6]).count();
+    runs > 1
+}
+
+// Serialized form of a flow field, sorted so that
+// the same field is always written the same way
+#[derive(Serialize, Deserialize)]
+struct FlowFieldTiles {
+    goal: Hex,
+    // tile, its cost and the next tile on the way
+    tiles: Vec<(Hex, u32, Option<Hex>)>,
+}
+
+impl From<FlowField> for FlowFieldTiles {
+    fn from(field: FlowField) -> Self {
+        let mut tiles: Vec<(Hex, u32, Option<Hex>)> = field
+            .costs
+            .iter()
+            .map(|(hex, cost)| (*hex, *cost, field.next.get(hex).copied()))
+            .collect();
+        tiles.sort_by_key(|(hex, ..)| (hex.x, hex.y));
+        Self {
+            goal: field.goal,
+            tiles,
+        }
+    }
+}
+
+impl From<FlowFieldTiles> for FlowField {
+    fn from(field: FlowFieldTiles) -> Self {
+        Self {
+            goal: field.goal,
+            costs: field
+                .tiles
+                .iter()
+                .map(|(hex, cost, _)| (*hex, *cost))
+                .collect(),
+            next: field
+                .tiles
+                .iter()
+                .filter_map(|(hex, _, next)| Some((*hex, (*next)?)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flow_field_leads_around_obstacles() {
+        let wall = [Hex::new(1, 0), Hex::new(1, -1), Hex::new(0, 1)];
+        let field = FlowField::new(Hex::ZERO, |hex| {
+            if hex.ulength() > 3 {
+                return None;
+            }
+            step_cost(TileType::Plains, wall.contains(&hex))
+        });
+        let start = Hex::new(3, -1);
+        let path = field.path(start).unwrap();
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&Hex::ZERO));
+        assert!(path.iter().all(|hex| !wall.contains(hex)));
+        assert!(path
+            .windows(2)
+            .all(|w| w[0].unsigned_distance_to(w[1]) == 1));
+        // every open tile of the board leads to the goal
+        assert!(Hex::ZERO
+            .spiral_range(1..=3)
+            .filter(|hex| !wall.contains(hex))
+            .all(|hex| field.path(hex).unwrap().last() == Some(&Hex::ZERO)));
+        assert_eq!(field.next_step(Hex::ZERO), None);
+        assert_eq!(field.path(Hex::new(4, 0)), None);
+        assert_eq!(field.path(wall[0]), None);
+        // stepping off a blocked tile
+        let out = field.next_step(wall[0]).unwrap();
+        assert!(field.cost(out).is_some() && out.unsigned_distance_to(wall[0]) == 1);
+
+        // walling the goal in disconnects everything
+        let field = FlowField::new(Hex::ZERO, |hex| {
+            if hex.ulength() > 3 {
+                return None;
+            }
+            step_cost(TileType::Plains, hex.ulength() == 1)
+        });
+        assert!(field.connects(&[Hex::ZERO]));
+        assert!(!field.connects(&[Hex::ZERO, Hex::new(3, 0)]));
+    }
+
+    #[test]
+    fn only_tiles_splitting_their_neighbours_may_disconnect() {
+        // a wall with a gap on both sides of the middle tile
+        let wall = [Hex::new(0, -1), Hex::new(0, 1)];
+        let is_open = |hex: Hex| hex.ulength() <= 3 && !wall.contains(&hex);
+        assert!(may_disconnect(Hex::ZERO, is_open));
+        assert!(!may_disconnect(Hex::new(2, 0), is_open));
+        // tiles at the edge of the board and at the end of a wall
+        assert!(!may_disconnect(Hex::new(3, 0), is_open));
+        assert!(!may_disconnect(Hex::new(1, -1), is_open));
+
+        // tiles that may not disconnect never do
+        let mountains = [
+            Hex::new(1, -2),
+            Hex::new(-2, 1),
+            Hex::new(2, 1),
+            Hex::new(-1, -1),
+            Hex::new(0, 2),
+        ];
+        let is_open = |hex: Hex| hex.ulength() <= 3 && !mountains.contains(&hex);
+        let open: Vec<Hex> = Hex::ZERO
+            .spiral_range(0..=3)
+            .filter(|h| is_open(*h))
+            .collect();
+        for blocked in open.iter().copied() {
+            let Some(goal) = open.iter().copied().find(|hex| *hex != blocked) else {
+                continue;
+            };
+            let field = FlowField::new(goal, |hex| (hex != blocked && is_open(hex)).then_some(1));
+            let others: Vec<Hex> = open.iter().copied().filter(|h| *h != blocked).collect();
+            if !may_disconnect(blocked, is_open) {
+                assert!(field.connects(&others), "{blocked:?}");
+            }
+        }
+    }
+}
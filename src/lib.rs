@@ -1,17 +1,36 @@
 use bevy::prelude::*;
 use bevy_egui::EguiPlugin;
-pub use communication::{parse_command, TDCommand};
-use config::GameConfig;
+pub use communication::{
+    parse_command, parse_request, CommandError, CommandRequest, CommandResponse, Reply, Requested,
+    TDCommand, TDQuery,
+};
+pub use config::GameConfig;
 use crossbeam_channel::{unbounded, Sender};
+pub use env::{Channel, Env, EnvConfig, Observation, RewardConfig};
+pub use query::*;
+pub use replay::Replay;
+pub use server::start_command_server;
 
 mod communication;
 mod components;
 mod config;
+mod env;
+mod flow_field;
+mod map;
+#[cfg(feature = "python")]
+mod python;
+mod query;
+mod replay;
 mod resources;
+mod server;
+mod snapshot;
 mod systems;
+#[cfg(test)]
+mod test_utils;
 mod utils;
 
 use communication::*;
+use components::TowerType;
 use resources::*;
 use systems::*;
 
@@ -35,13 +54,13 @@ pub enum AppState {
 pub fn setup_tower_defense() -> (App, Sender<TDCommand>) {
     // load game config
     let config = GameConfig::load(CONFIG_PATH);
+    setup_tower_defense_with_config(config)
+}
 
+pub fn setup_tower_defense_with_config(config: GameConfig) -> (App, Sender<TDCommand>) {
     // setup app
     let mut app = App::new();
 
-    // setup app channels to communicate from
-    // outside of the bevy engine
-    let (tx, rx) = unbounded::<TDCommand>();
     // Plugins
     app.add_plugins(DefaultPlugins.set(WindowPlugin {
         primary_window: Some(Window {
@@ -53,70 +72,489 @@ pub fn setup_tower_defense() -> (App, Sender<TDCommand>) {
         ..default()
     }))
     .add_plugin(EguiPlugin)
-    // State
-    .add_state::<AppState>()
     // Resources
-    .insert_resource(ClearColor(Color::rgb(0.1, 0.0, 0.0)))
-    .insert_resource(GameCommandChannel(rx))
-    .insert_resource(SelectedTower::default())
-    .insert_resource(Config(config))
-    // Events
-    .add_event::<TDCommand>()
-    .add_event::<CreateTower>()
-    .add_event::<RecalculateEnemyPaths>()
-    .add_event::<Restart>()
-    .add_event::<GameOver>()
-    .add_event::<ToggleTile>()
-    .add_event::<RefreshTowerDamage>()
-    // Systems
+    .insert_resource(ClearColor(Color::rgb(0.1, 0.0, 0.0)));
+
+    let tx = add_simulation(&mut app, config);
+
+    // Rendering, input and ui systems
+    app.add_system(setup_camera.in_schedule(OnEnter(AppState::Setup)))
+        .add_systems(
+            (
+                camera_zoom,
+                handle_input.run_if(not(resource_exists::<ReplayPlayer>())),
+                show_ui,
+                render_tiles,
+                render_tower_aoe,
+                tint_enemies,
+            )
+                .in_set(OnUpdate(AppState::InGame)),
+        )
+        .add_systems(
+            (
+                show_ui,
+                handle_input.run_if(not(resource_exists::<ReplayPlayer>())),
+                camera_zoom,
+            )
+                .in_set(OnUpdate(AppState::Pause)),
+        )
+        .add_system(show_game_over_text.in_schedule(OnEnter(AppState::GameOver)));
+
+    (app, tx)
+}
+
+// Sets up the game without a window, renderer or ui so the simulation
+// can be driven from tests or on machines without a display
+pub fn setup_tower_defense_headless(config: GameConfig) -> (App, Sender<TDCommand>) {
+    let mut app = App::new();
+
+    // Plugins
+    // the asset plugin is only needed to hold the (never rendered) meshes
+    // and materials that the simulation systems attach to entities
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin::default())
+        .add_asset::<Mesh>()
+        .add_asset::<ColorMaterial>();
+
+    let tx = add_simulation(&mut app, config);
+
+    (app, tx)
+}
+
+// Sender for commands whose outcome is sent back to the `reply` of the command
+pub fn request_sender(app: &App) -> Sender<Requested<TDCommand>> {
+    app.world.resource::<GameRequestSender>().0.clone()
+}
+
+// Records every command executed by the game, together with
+// the seed and config, into a replay file at `path`
+pub fn record_replay(app: &mut App, path: &str) {
+    app.insert_resource(CommandRecorder {
+        path: path.to_string(),
+        replay: None,
+    });
+}
+
+// Sends the commands of `replay` to the game on the ticks they were recorded on,
+// the app has to be setup with `replay.config()` to reproduce the game
+pub fn play_replay(app: &mut App, replay: Replay) {
+    app.insert_resource(ReplayPlayer {
+        commands: replay.into_queue(),
+    });
+}
+
+// Sets that order the simulation systems within a fixed tick
+#[derive(SystemSet, PartialEq, Eq, Debug, Clone, Hash)]
+pub enum SimulationSet {
+    Commands,
+    Board,
+    Enemies,
+    Timers,
+}
+
+// The sets of a tick, held back while paths due on it are still being calculated
+#[derive(SystemSet, PartialEq, Eq, Debug, Clone, Hash)]
+pub struct TickSet;
+
+// whether there is a board, running or not
+fn board_is_up() -> impl Condition<()> {
+    in_state(AppState::InGame)
+        .or_else(in_state(AppState::Pause))
+        .or_else(in_state(AppState::GameOver))
+}
+
+// Adds the state, resources, events and systems that make up the game simulation
+fn add_simulation(app: &mut App, config: GameConfig) -> Sender<TDCommand> {
+    // configs built in code or read from replays skip `GameConfig::load`
+    let config = config.validated();
+    // setup app channels to communicate from
+    // outside of the bevy engine
+    let (tx, rx) = unbounded::<TDCommand>();
+    let (request_tx, request_rx) = unbounded::<Requested<TDCommand>>();
+    // start with the first tower kind of the config selected
+    let selected_tower = config.tower_config.tower_types.keys().next().cloned();
+
+    // State
+    app.add_state::<AppState>()
+        // Resources
+        .insert_resource(FixedTime::new_from_secs(config.tick_length))
+        .insert_resource(GameCommandChannel(rx))
+        .insert_resource(GameCommandSender(tx.clone()))
+        .insert_resource(GameRequestChannel(request_rx))
+        .insert_resource(GameRequestSender(request_tx))
+        .insert_resource(SimulationTick::default())
+        .insert_resource(SelectedTower {
+            selected: TowerType(selected_tower.unwrap_or_default()),
+        })
+        .insert_resource(Config(config))
+        // Events
+        .add_event::<Requested<TDCommand>>()
+        .add_event::<Requested<CreateTower>>()
+        .add_event::<Requested<UpgradeTower>>()
+        .add_event::<Requested<SellTower>>()
+        .add_event::<Requested<SetTargeting>>()
+        .add_event::<RecalculateEnemyPaths>()
+        .add_event::<Requested<Restart>>()
+        .add_event::<GameOver>()
+        .add_event::<Requested<ToggleTile>>()
+        .add_event::<RefreshTowerDamage>()
+        .add_event::<Requested<SaveGame>>()
+        .add_event::<Requested<LoadGame>>()
+        .add_event::<Requested<TDQuery>>()
+        .add_event::<CommandResult>()
+        // Systems
+        .add_systems(
+            (setup_resources, apply_system_buffers, spawn_board_and_tiles)
+                .chain()
+                .in_schedule(OnEnter(AppState::Setup)),
+        )
+        .add_system(recalculate_enemy_path.in_schedule(OnEnter(AppState::InGame)))
+        .add_system(report_command_results)
+        // after anything in the frame asked the app to exit
+        .add_system(
+            write_replay
+                .run_if(resource_exists::<CommandRecorder>())
+                .in_base_set(CoreSet::Last),
+        )
+        .add_systems(
+            (destroy_board, spawn_board_and_tiles)
+                .chain()
+                .in_schedule(OnEnter(AppState::Restart)),
+        );
+
+    // The simulation runs on a fixed tick with explicit ordering so that the same
+    // seed and command stream always produce the same game
+    app.edit_schedule(CoreSchedule::FixedUpdate, |schedule| {
+        schedule.configure_sets(
+            (
+                SimulationSet::Commands,
+                SimulationSet::Board,
+                SimulationSet::Enemies,
+                SimulationSet::Timers,
+            )
+                .chain(),
+        );
+        // commands are read whenever there is a board, to answer
+        // queries while the game is paused or over as well
+        schedule.configure_set(SimulationSet::Commands.run_if(board_is_up()));
+        for set in [
+            SimulationSet::Board,
+            SimulationSet::Enemies,
+            SimulationSet::Timers,
+        ] {
+            schedule.configure_set(set.run_if(in_state(AppState::InGame)));
+        }
+        // checked once before the tick, so that a tick runs either whole or not at all
+        schedule.configure_set(TickSet.run_if(paths_are_ready));
+        for set in [
+            SimulationSet::Commands,
+            SimulationSet::Board,
+            SimulationSet::Enemies,
+            SimulationSet::Timers,
+        ] {
+            schedule.configure_set(set.in_set(TickSet));
+        }
+    })
     .add_systems(
         (
-            setup_camera,
-            setup_resources,
+            advance_simulation_tick.run_if(in_state(AppState::InGame)),
+            replay_commands.run_if(resource_exists::<ReplayPlayer>()),
+            event_dispatch.run_if(not(resource_exists::<ReplayPlayer>())),
+            record_commands.run_if(resource_exists::<CommandRecorder>()),
+            execute_outside_commands,
+            receive_restart_command,
+            save_game,
+            load_game,
             apply_system_buffers,
-            spawn_board_and_tiles,
         )
             .chain()
-            .in_schedule(OnEnter(AppState::Setup)),
+            .in_set(SimulationSet::Commands)
+            .in_schedule(CoreSchedule::FixedUpdate),
     )
-    .add_system(recalculate_enemy_path.in_schedule(OnEnter(AppState::InGame)))
     .add_systems(
         (
-            camera_zoom,
-            event_dispatch,
-            execute_outside_commands,
-            handle_input,
-            handle_enemy_damage,
-            handle_enemy_movement,
-            handle_new_towers,
-            handle_removed_paths,
-            show_ui,
-            spawn_enemies,
+            toggle_tile,
             spawn_tower,
+            upgrade_tower,
+            sell_tower,
+            set_targeting,
+            // sold towers no longer block paths
+            apply_system_buffers,
             recalculate_enemy_path,
-            receive_restart_command,
-            render_tiles,
-            render_tower_aoe,
+            apply_system_buffers,
+            apply_enemy_paths,
+            apply_system_buffers,
+            handle_removed_paths,
+            refresh_damaging_tiles,
+            apply_system_buffers,
+            handle_new_towers,
+            apply_system_buffers,
         )
-            .in_set(OnUpdate(AppState::InGame)),
+            .chain()
+            .in_set(SimulationSet::Board)
+            .in_schedule(CoreSchedule::FixedUpdate),
     )
     .add_systems(
         (
-            game_timer,
-            toggle_tile,
-            refresh_damaging_tiles,
-            remove_towers_on_path,
+            spawn_enemies,
+            handle_enemy_movement,
+            fire_projectiles,
+            move_projectiles,
+            tick_status_effects,
+            handle_enemy_damage,
+            apply_system_buffers,
         )
-            .in_set(OnUpdate(AppState::InGame)),
-    )
-    .add_systems((show_ui, handle_input, camera_zoom).in_set(OnUpdate(AppState::Pause)))
-    .add_systems(
-        (destroy_board, spawn_board_and_tiles)
             .chain()
-            .in_schedule(OnEnter(AppState::Restart)),
+            .in_set(SimulationSet::Enemies)
+            .in_schedule(CoreSchedule::FixedUpdate),
+    )
+    .add_system(
+        game_timer
+            .in_set(SimulationSet::Timers)
+            .in_schedule(CoreSchedule::FixedUpdate),
+    )
+    .add_system(
+        answer_queries
+            .run_if(board_is_up())
+            .after(SimulationSet::Timers)
+            .in_schedule(CoreSchedule::FixedUpdate),
     )
-    .add_system(show_game_over_text.in_schedule(OnEnter(AppState::GameOver)))
-    .add_system(game_over_timer.in_set(OnUpdate(AppState::GameOver)));
+    .add_system(
+        game_over_timer
+            .run_if(in_state(AppState::GameOver))
+            .in_schedule(CoreSchedule::FixedUpdate),
+    )
+    // apply state changes at the end of the tick so that following
+    // ticks in the same frame already run in the new state
+    .add_system(
+        apply_state_transition::<AppState>
+            .after(SimulationSet::Timers)
+            .after(game_over_timer)
+            .after(answer_queries)
+            .in_schedule(CoreSchedule::FixedUpdate),
+    );
 
-    (app, tx)
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::Budget;
+    use crate::components::Coords;
+    use crate::components::GameResult;
+    use crate::components::IsGoal;
+    use crate::components::Lives;
+    use crate::components::Outcome;
+    use crate::components::TDPaths;
+    use crate::components::Tile;
+    use crate::components::TileType;
+    use crate::components::Tower;
+    use crate::test_utils::*;
+    use bevy::time::TimeUpdateStrategy;
+    use hexx::Hex;
+    use std::time::Instant;
+
+    #[test]
+    fn headless_game_starts() {
+        let (mut app, _tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        assert_eq!(app.world.resource::<State<AppState>>().0, AppState::InGame);
+
+        let mut paths = app.world.query::<&TDPaths>();
+        let td_paths = paths.single(&app.world);
+        assert_eq!(
+            td_paths.paths.as_ref().map(|p| p.len()),
+            Some(td_paths.spawns.len())
+        );
+    }
+
+    #[test]
+    fn requests_are_answered() {
+        let (mut app, _tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        let goal = app.world.query::<&TDPaths>().single(&app.world).goal;
+        let mut tiles = app.world.query::<(&Coords, &Tile)>();
+        let mut open: Vec<Hex> = tiles
+            .iter(&app.world)
+            .filter(|(coords, tile)| {
+                tile.tile_type == TileType::Plains && coords.0.unsigned_distance_to(goal) == 3
+            })
+            .map(|(coords, _)| coords.0)
+            .collect();
+        open.sort_by_key(|hex| (hex.x, hex.y));
+
+        let requests = request_sender(&app);
+        let (reply_tx, reply_rx) = unbounded();
+        let send = |id: u64, command: &str| {
+            requests
+                .send(Requested {
+                    command: parse_command(command).unwrap(),
+                    reply: Some(Reply {
+                        id: Some(id),
+                        tx: reply_tx.clone(),
+                    }),
+                })
+                .unwrap();
+        };
+        let (tile, other) = (open[0], open[1]);
+        send(1, &format!("tower {},{} Small", goal.x, goal.y));
+        send(2, "toggle 100,100");
+        send(3, &format!("tower {},{} Huge", tile.x, tile.y));
+        send(4, &format!("upgrade {},{}", tile.x, tile.y));
+        // short names stand for their kind
+        send(5, &format!("tower {},{} s", tile.x, tile.y));
+        run_until_tick(&mut app, 2, frame_length);
+        let tower = app.world.query::<&Tower>().single(&app.world);
+        assert_eq!(tower.tower_type.0, "Small");
+        app.world
+            .query::<&mut Budget>()
+            .single_mut(&mut app.world)
+            .0 = 0;
+        send(6, &format!("tower {},{} Small", other.x, other.y));
+        send(7, &format!("sell {},{}", tile.x, tile.y));
+        run_until_tick(&mut app, 3, frame_length);
+
+        let mut responses: Vec<CommandResponse> = reply_rx.try_iter().collect();
+        responses.sort_by_key(|response| response.id);
+        let errors: Vec<(Option<u64>, Option<CommandError>)> = responses
+            .into_iter()
+            .map(|response| (response.id, response.error))
+            .collect();
+        let cost = app.world.resource::<Config>().0.tower_config.tower_types["Small"].cost;
+        assert_eq!(
+            errors,
+            vec![
+                (Some(1), Some(CommandError::TileOccupied(goal))),
+                (Some(2), Some(CommandError::OutOfBounds(Hex::new(100, 100)))),
+                (
+                    Some(3),
+                    Some(CommandError::UnknownTowerType {
+                        name: "Huge".to_string(),
+                        kinds: app.world.resource::<Config>().0.tower_config.kinds(),
+                    })
+                ),
+                (Some(4), Some(CommandError::NoTower(tile))),
+                (Some(5), None),
+                (
+                    Some(6),
+                    Some(CommandError::InsufficientBudget { budget: 0, cost })
+                ),
+                (Some(7), None),
+            ]
+        );
+    }
+
+    // runs until the game is over, returning how it ended
+    fn run_until_game_over(app: &mut App, max_ticks: u64) -> Option<components::Outcome> {
+        let frame_length = tick_length(app);
+        run_until_tick(app, 1, frame_length);
+        let mut tick = 1;
+        while tick < max_ticks && app.world.resource::<State<AppState>>().0 == AppState::InGame {
+            tick += 1;
+            run_until_tick(app, tick, frame_length);
+        }
+        app.world.query::<&GameResult>().single(&app.world).0
+    }
+
+    #[test]
+    fn leaking_enemies_end_the_game() {
+        let mut config = GameConfig {
+            seed: Some(42),
+            ..default()
+        };
+        config.outcome.lives = 3;
+        config.outcome.win_on_timer = false;
+        let (mut app, _tx) = setup_tower_defense_headless(config);
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+
+        assert_eq!(
+            run_until_game_over(&mut app, 5000),
+            Some(components::Outcome::Defeat)
+        );
+        let mut goal = app.world.query_filtered::<&Lives, With<IsGoal>>();
+        assert_eq!(goal.single(&app.world).0, 0);
+    }
+
+    #[test]
+    fn surviving_the_timer_wins() {
+        let config = GameConfig {
+            seed: Some(42),
+            game_length: 1.,
+            ..default()
+        };
+        let (mut app, _tx) = setup_tower_defense_headless(config);
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+
+        assert_eq!(
+            run_until_game_over(&mut app, 200),
+            Some(components::Outcome::Victory)
+        );
+        assert_eq!(app.world.resource::<SimulationTick>().0, 60);
+    }
+
+    #[test]
+    fn queries_are_answered_after_the_game_ends() {
+        let config = GameConfig {
+            seed: Some(42),
+            game_length: 1.,
+            ..default()
+        };
+        let (mut app, _tx) = setup_tower_defense_headless(config);
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        run_until_game_over(&mut app, 200);
+
+        let requests = request_sender(&app);
+        let (reply_tx, reply_rx) = unbounded();
+        for (id, command) in [(1, "state"), (2, "toggle 1,0")] {
+            requests
+                .send(Requested {
+                    command: parse_command(command).unwrap(),
+                    reply: Some(Reply {
+                        id: Some(id),
+                        tx: reply_tx.clone(),
+                    }),
+                })
+                .unwrap();
+        }
+        // the game over screen lasts for a few seconds before the next game
+        let frame_length = tick_length(&app);
+        run_frame(&mut app, frame_length);
+        assert_eq!(
+            app.world.resource::<State<AppState>>().0,
+            AppState::GameOver
+        );
+
+        let mut responses: Vec<CommandResponse> = reply_rx.try_iter().collect();
+        responses.sort_by_key(|response| response.id);
+        let [state, toggle] = responses.as_slice() else {
+            panic!("expected two responses, got {responses:?}");
+        };
+        let Some(QueryResult::State(state)) = &state.data else {
+            panic!("expected the state, got {state:?}");
+        };
+        assert_eq!((state.tick, state.result), (60, Some(Outcome::Victory)));
+        assert_eq!(toggle.error, Some(CommandError::GameNotRunning));
+    }
+
+    // board state after a fixed command stream, `ticks_per_frame`
+    // has to divide the ticks commands are sent on
+    fn simulate(ticks_per_frame: u32) -> (u32, u32, u32, Vec<(u32, u32)>) {
+        let (mut app, tx) = headless_app(7);
+        let frame_length = tick_length(&app) * ticks_per_frame;
+
+        run_until_tick(&mut app, ticks_per_frame as u64, frame_length);
+        send_path_toggles(&mut app, &tx, frame_length);
+        run_until_tick(&mut app, 600, frame_length);
+        board_state(&mut app)
+    }
+
+    #[test]
+    fn simulation_is_deterministic() {
+        let (budget, player_score, enemy_score, positions) = simulate(1);
+        assert!(!positions.is_empty());
+        assert_eq!((budget, player_score, enemy_score, positions), simulate(3));
+    }
 }
@@ -1,22 +1,81 @@
-use crossbeam_channel::Sender;
-use std::{io::stdin, thread, time::Duration};
-use tower_defense_bevy_excercise::{parse_command, setup_tower_defense, TDCommand};
+use crossbeam_channel::{unbounded, Sender};
+use std::{env, io::stdin, thread, time::Duration};
+use tower_defense_bevy_excercise::{
+    parse_command, play_replay, record_replay, request_sender, setup_tower_defense_with_config,
+    start_command_server, GameConfig, Replay, Reply, Requested, TDCommand, CONFIG_PATH,
+};
 use tracing::{event, span, Level};
 
+// see the usage section of the readme
+const USAGE: &str =
+    "usage: tower-defense-bevy-excercise [--record <file> | --replay <file>] [--port <port>]";
+
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    let flag_value = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|i| args.get(i + 1))
+    };
+
+    if let Some(path) = flag_value("--replay") {
+        let replay = match Replay::load(path) {
+            Ok(replay) => replay,
+            Err(e) => {
+                eprintln!("{USAGE}\n--replay takes a replay written with --record: {e}");
+                std::process::exit(2);
+            }
+        };
+        let (mut app, _tx) = setup_tower_defense_with_config(replay.config());
+        play_replay(&mut app, replay);
+        app.run();
+        return;
+    }
+
     // setup app and sender
-    let (mut app, tx) = setup_tower_defense();
+    let config = GameConfig::load(CONFIG_PATH);
+    let port = match flag_value("--port").map(|port| port.parse::<u16>()) {
+        Some(Ok(port)) => Some(port),
+        Some(Err(e)) => {
+            eprintln!("{USAGE}\n--port takes a port from 0 to 65535: {e}");
+            std::process::exit(2);
+        }
+        None => config.command_server_port,
+    };
+    let (mut app, tx) = setup_tower_defense_with_config(config);
+    if let Some(path) = flag_value("--record") {
+        record_replay(&mut app, path);
+    }
+    if let Some(port) = port {
+        if let Err(e) = start_command_server(port, tx.clone(), request_sender(&app)) {
+            event!(
+                Level::WARN,
+                "Unable to start command server on port {port}: {e}"
+            );
+        }
+    }
     // spawn thread with command receive loop
-    let _command_channel_handle = thread::spawn(move || command_receive_loop(tx));
+    let requests = request_sender(&app);
+    let _command_channel_handle = thread::spawn(move || command_receive_loop(tx, requests));
     // run app
     app.run();
     _command_channel_handle.join().unwrap();
 }
 
-fn command_receive_loop(tx: Sender<TDCommand>) {
+fn command_receive_loop(tx: Sender<TDCommand>, requests: Sender<Requested<TDCommand>>) {
     let span = span!(Level::INFO, "TDCommand channel");
     let _guard = span.enter();
 
+    // answers to queries are printed as they come in
+    let (reply_tx, reply_rx) = unbounded();
+    thread::spawn(move || {
+        for response in reply_rx {
+            if let Ok(json) = serde_json::to_string(&response) {
+                println!("{json}");
+            }
+        }
+    });
+
     loop {
         let stdin = stdin();
         let mut buffer = String::new();
@@ -28,6 +87,18 @@ fn command_receive_loop(tx: Sender<TDCommand>) {
 
         event!(Level::DEBUG, "Read input: {buffer}");
         match parse_command(buffer.trim_end()) {
+            Some(TDCommand::Query(query)) => {
+                let request = Requested {
+                    command: TDCommand::Query(query),
+                    reply: Some(Reply {
+                        id: None,
+                        tx: reply_tx.clone(),
+                    }),
+                };
+                if requests.try_send(request).is_err() {
+                    event!(Level::DEBUG, "Unable to send query to receiver (main)");
+                }
+            }
             Some(td_command) => {
                 if tx.try_send(td_command).is_ok() {
                     event!(Level::DEBUG, "Command sent (main)");
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use hexx::Hex;
+use serde::{Deserialize, Serialize};
+
+use crate::components::{TileType, TowerType};
+use crate::flow_field::{step_cost, FlowField};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct MapTile {
+    pub hex: Hex,
+    pub tile_type: TileType,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MapTower {
+    pub hex: Hex,
+    pub tower_type: TowerType,
+}
+
+// A board layout, either hand authored in a map file or procedurally generated
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HexMap {
+    // fills a hexagon of this radius around `Hex::ZERO` with plains
+    pub radius: Option<u32>,
+    // tiles added to, or replacing tiles of, the hexagon
+    #[serde(default)]
+    pub tiles: Vec<MapTile>,
+    pub spawns: Vec<Hex>,
+    pub goal: Hex,
+    // towers placed at the start of the game, free of cost
+    #[serde(default)]
+    pub towers: Vec<MapTower>,
+}
+
+impl HexMap {
+    pub fn load(path: &str) -> Result<Self, &'static str> {
+        let map_yaml = std::fs::read_to_string(path).map_err(|_| "Unable to read map file")?;
+        let map: HexMap =
+            serde_yaml::from_str(&map_yaml).map_err(|_| "Unable to deserialize map")?;
+        map.validate()?;
+        Ok(map)
+    }
+
+    // all tiles of the map in the order they are spawned
+    pub fn all_tiles(&self) -> Vec<MapTile> {
+        let mut tiles: Vec<MapTile> = match self.radius {
+            Some(radius) => Hex::ZERO
+                .spiral_range(0..=radius)
+                .map(|hex| MapTile {
+                    hex,
+                    tile_type: TileType::Plains,
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut indices: HashMap<Hex, usize> =
+            tiles.iter().enumerate().map(|(i, t)| (t.hex, i)).collect();
+        for tile in self.tiles.iter() {
+            if let Some(i) = indices.get(&tile.hex) {
+                tiles[*i] = *tile;
+            } else {
+                indices.insert(tile.hex, tiles.len());
+                tiles.push(*tile);
+            }
+        }
+        tiles
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let tiles: HashMap<Hex, TileType> = self
+            .all_tiles()
+            .into_iter()
+            .map(|t| (t.hex, t.tile_type))
+            .collect();
+        if !tiles.contains_key(&self.goal) {
+            return Err("Map goal is not on a tile");
+        }
+        if self.spawns.is_empty() {
+            return Err("Map has no spawns");
+        }
+        if self
+            .spawns
+            .iter()
+            .any(|s| !tiles.contains_key(s) || *s == self.goal)
+        {
+            return Err("Map spawn is not on a tile or on the goal");
+        }
+        if self.towers.iter().any(|t| {
+            !tiles.contains_key(&t.hex) || t.hex == self.goal || self.spawns.contains(&t.hex)
+        }) {
+            return Err("Map tower is not on a tile or on the goal or a spawn");
+        }
+        // every spawn needs a way to the goal around mountains and towers
+        let flow_field = FlowField::new(self.goal, |hex| {
+            let has_tower = self.towers.iter().any(|t| t.hex == hex);
+            step_cost(*tiles.get(&hex)?, has_tower)
+        });
+        if !flow_field.connects(&self.spawns) {
+            return Err("Map spawn is not connected to the goal");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_example_map() {
+        let map = HexMap::load("config/map.yaml").unwrap();
+        let tiles = map.all_tiles();
+        assert_eq!(tiles.first().map(|t| t.hex), Some(Hex::ZERO));
+        assert!(tiles.iter().any(|t| t.tile_type == TileType::Mountain));
+    }
+
+    #[test]
+    fn reject_disconnected_spawn() {
+        let map = HexMap {
+            radius: None,
+            tiles: vec![Hex::ZERO, Hex::new(2, 0)]
+                .into_iter()
+                .map(|hex| MapTile {
+                    hex,
+                    tile_type: TileType::Plains,
+                })
+                .collect(),
+            spawns: vec![Hex::new(2, 0)],
+            goal: Hex::ZERO,
+            towers: vec![],
+        };
+        assert!(map.validate().is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+
+use hexx::Hex;
+use pyo3::exceptions::{PyBufferError, PyValueError};
+use pyo3::ffi;
+use pyo3::prelude::*;
+
+use crate::communication::*;
+use crate::components::TowerType;
+use crate::config::GameConfig;
+use crate::env::{Channel, Env, EnvConfig, Observation, RewardConfig};
+
+// A game stepped from python, see `Env`
+#[pyclass(name = "Env", unsendable)]
+struct PyEnv(Env);
+
+#[pymethods]
+impl PyEnv {
+    #[new]
+    #[pyo3(signature = (seed=0, ticks_per_step=10, config_path=None, player_score_reward=1.0, enemy_score_reward=-1.0))]
+    fn new(
+        seed: u64,
+        ticks_per_step: u64,
+        config_path: Option<&str>,
+        player_score_reward: f32,
+        enemy_score_reward: f32,
+    ) -> Self {
+        let game = config_path.map(GameConfig::load).unwrap_or_default();
+        Self(Env::new(EnvConfig {
+            game: GameConfig {
+                seed: Some(seed),
+                ..game
+            },
+            ticks_per_step,
+            reward: RewardConfig {
+                player_score: player_score_reward,
+                enemy_score: enemy_score_reward,
+            },
+        }))
+    }
+
+    fn reset(&mut self, seed: u64) -> PyObservation {
+        self.0.reset(seed).into()
+    }
+
+    // returns the observation, the reward and whether the game is over
+    fn step(&mut self, actions: Vec<PyAction>) -> (PyObservation, f32, bool) {
+        let actions: Vec<TDCommand> = actions.into_iter().map(|action| action.0).collect();
+        let (observation, reward, done) = self.0.step(&actions);
+        (observation.into(), reward, done)
+    }
+
+    fn observe(&mut self) -> PyObservation {
+        self.0.observe().into()
+    }
+
+    #[getter]
+    fn done(&self) -> bool {
+        self.0.done()
+    }
+}
+
+// An observation whose grid is readable as a buffer of float32, e.g. with
+// `numpy.asarray(observation)`, shaped as channels, rows and columns
+#[pyclass(name = "Observation", frozen)]
+struct PyObservation {
+    #[pyo3(get)]
+    tick: u64,
+    #[pyo3(get)]
+    budget: u32,
+    #[pyo3(get)]
+    player_score: u32,
+    #[pyo3(get)]
+    enemy_score: u32,
+    #[pyo3(get)]
+    lives: u32,
+    #[pyo3(get)]
+    radius: u32,
+    grid: Vec<f32>,
+    // pointed to by the buffers handed out
+    shape: [isize; 3],
+    strides: [isize; 3],
+}
+
+impl From<Observation> for PyObservation {
+    fn from(observation: Observation) -> Self {
+        let shape = observation.shape().map(|n| n as isize);
+        let item = std::mem::size_of::<f32>() as isize;
+        Self {
+            tick: observation.tick,
+            budget: observation.budget,
+            player_score: observation.player_score,
+            enemy_score: observation.enemy_score,
+            lives: observation.lives,
+            radius: observation.radius,
+            shape,
+            strides: [shape[1] * shape[2] * item, shape[2] * item, item],
+            grid: observation.grid,
+        }
+    }
+}
+
+#[pymethods]
+impl PyObservation {
+    #[getter]
+    fn shape(&self) -> (isize, isize, isize) {
+        (self.shape[0], self.shape[1], self.shape[2])
+    }
+
+    // the grid is read only, python keeps the observation alive while it is viewed
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        if view.is_null() {
+            return Err(PyBufferError::new_err("View is null"));
+        }
+        if (flags & ffi::PyBUF_WRITABLE) == ffi::PyBUF_WRITABLE {
+            return Err(PyBufferError::new_err("Observations are read only"));
+        }
+        let observation = slf.get();
+        (*view).buf = observation.grid.as_ptr() as *mut c_void;
+        (*view).len = std::mem::size_of_val(observation.grid.as_slice()) as isize;
+        (*view).readonly = 1;
+        (*view).itemsize = std::mem::size_of::<f32>() as isize;
+        (*view).format = if (flags & ffi::PyBUF_FORMAT) == ffi::PyBUF_FORMAT {
+            c"f".as_ptr() as *mut _
+        } else {
+            ptr::null_mut()
+        };
+        // without a shape the buffer is seen as plain bytes
+        if (flags & ffi::PyBUF_ND) == ffi::PyBUF_ND {
+            (*view).ndim = 3;
+            (*view).shape = observation.shape.as_ptr() as *mut isize;
+        } else {
+            (*view).ndim = 1;
+            (*view).shape = ptr::null_mut();
+        }
+        (*view).strides = if (flags & ffi::PyBUF_STRIDES) == ffi::PyBUF_STRIDES {
+            observation.strides.as_ptr() as *mut isize
+        } else {
+            ptr::null_mut()
+        };
+        (*view).suboffsets = ptr::null_mut();
+        (*view).internal = ptr::null_mut();
+        (*view).obj = slf.into_any().into_ptr();
+        Ok(())
+    }
+
+    unsafe fn __releasebuffer__(&self, _view: *mut ffi::Py_buffer) {}
+}
+
+// A `TDCommand` to send with a step
+#[pyclass(name = "Action", frozen)]
+#[derive(Clone)]
+struct PyAction(TDCommand);
+
+#[pymethods]
+impl PyAction {
+    // an action in the line format of stdin, e.g. `tower 1,2 Small`
+    #[staticmethod]
+    fn parse(line: &str) -> PyResult<Self> {
+        parse_command(line)
+            .map(Self)
+            .ok_or_else(|| PyValueError::new_err(format!("Invalid command: {line}")))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.0)
+    }
+}
+
+#[pyfunction]
+fn toggle(x: i32, y: i32) -> PyAction {
+    PyAction(TDCommand::Toggle(ToggleTile {
+        hex_pos: Hex { x, y },
+    }))
+}
+
+#[pyfunction]
+fn tower(x: i32, y: i32, tower_type: String) -> PyAction {
+    PyAction(TDCommand::Tower(CreateTower {
+        tower_type: TowerType(tower_type),
+        hex_pos: Hex { x, y },
+    }))
+}
+
+#[pyfunction]
+fn upgrade(x: i32, y: i32) -> PyAction {
+    PyAction(TDCommand::Upgrade(UpgradeTower {
+        hex_pos: Hex { x, y },
+    }))
+}
+
+#[pyfunction]
+fn sell(x: i32, y: i32) -> PyAction {
+    PyAction(TDCommand::Sell(SellTower {
+        hex_pos: Hex { x, y },
+    }))
+}
+
+// `mode` is one of first, last, strongest, weakest or closest
+#[pyfunction]
+fn target(x: i32, y: i32, mode: &str) -> PyResult<PyAction> {
+    PyAction::parse(&format!("target {x},{y} {mode}"))
+}
+
+#[pyfunction]
+fn restart() -> PyAction {
+    PyAction(TDCommand::Restart(Restart))
+}
+
+#[pyfunction]
+fn save(path: String) -> PyAction {
+    PyAction(TDCommand::Save(SaveGame { path }))
+}
+
+#[pyfunction]
+fn load(path: String) -> PyAction {
+    PyAction(TDCommand::Load(LoadGame { path }))
+}
+
+// The `tower_defense` python module, built with maturin from `pyproject.toml`
+#[pymodule]
+fn tower_defense(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyEnv>()?;
+    m.add_class::<PyObservation>()?;
+    m.add_class::<PyAction>()?;
+    for function in [
+        wrap_pyfunction!(toggle, m)?,
+        wrap_pyfunction!(tower, m)?,
+        wrap_pyfunction!(upgrade, m)?,
+        wrap_pyfunction!(sell, m)?,
+        wrap_pyfunction!(target, m)?,
+        wrap_pyfunction!(restart, m)?,
+        wrap_pyfunction!(save, m)?,
+        wrap_pyfunction!(load, m)?,
+    ] {
+        m.add_function(function)?;
+    }
+    // names of the channels of the observation grid, in order
+    let channels: Vec<String> = Channel::ALL
+        .iter()
+        .map(|channel| format!("{channel:?}"))
+        .collect();
+    m.add("CHANNELS", channels)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observations_are_viewed_as_channels_rows_and_columns() {
+        let observation = Observation {
+            tick: 3,
+            budget: 10,
+            player_score: 1,
+            enemy_score: 2,
+            lives: 5,
+            radius: 20,
+            grid: vec![0.0; Channel::ALL.len() * 41 * 41],
+        };
+        let observation = PyObservation::from(observation);
+        assert_eq!(observation.shape(), (9, 41, 41));
+        // strides are in bytes, the columns of a row lie next to each other
+        assert_eq!(observation.strides, [41 * 41 * 4, 41 * 4, 4]);
+        assert_eq!(
+            observation.grid.len() as isize * 4,
+            observation.shape[0] * observation.strides[0]
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::Vec2;
+use hexx::Hex;
+use serde::{Deserialize, Serialize};
+
+use crate::components::*;
+
+// Answer to a `TDQuery`, sent as the data of the response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum QueryResult {
+    State(StateView),
+    Tile(TileView),
+    Enemies(Vec<EnemyView>),
+    Towers(Vec<TowerView>),
+    Paths(PathsView),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateView {
+    pub tick: u64,
+    pub budget: u32,
+    pub player_score: u32,
+    pub enemy_score: u32,
+    pub lives: u32,
+    // seconds of the game played and how long it lasts
+    pub elapsed: f32,
+    pub game_length: f32,
+    // share of the game played, from 0 to 1
+    pub progress: f32,
+    // index of the current wave
+    pub wave: usize,
+    pub waves: usize,
+    // none while the game is still going
+    pub result: Option<Outcome>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TileView {
+    pub hex: Hex,
+    pub tile_type: TileType,
+    pub is_goal: bool,
+    pub is_spawn: bool,
+    pub on_path: bool,
+    // damage dealt to enemies on the tile, none if out of range of every tower
+    pub damaging: Option<u32>,
+    pub tower: Option<TowerView>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TowerView {
+    pub hex: Hex,
+    pub tower_type: String,
+    pub level: u32,
+    // spent on the tower including upgrades
+    pub cost: u32,
+    pub targeting: TargetingMode,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnemyView {
+    pub enemy_type: String,
+    pub health: u32,
+    pub max_health: u32,
+    // the enemy is `lerp` of the way from one tile to the next
+    pub from: Hex,
+    pub to: Hex,
+    pub lerp: f32,
+    // in world units
+    pub position: Vec2,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathsView {
+    pub goal: Hex,
+    pub spawns: Vec<Hex>,
+    // the way from each spawn to the goal, none before the first calculation
+    pub paths: Option<BTreeMap<usize, Vec<Hex>>>,
+}
+
+impl From<&TDPaths> for PathsView {
+    fn from(td_paths: &TDPaths) -> Self {
+        Self {
+            goal: td_paths.goal,
+            spawns: td_paths.spawns.clone(),
+            paths: td_paths.paths.clone(),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{communication::TDCommand, config::GameConfig};
+
+// A command together with the simulation tick it was executed on
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecordedCommand {
+    pub tick: u64,
+    // replays send the command in the same state
+    #[serde(default)]
+    pub state: RecordedState,
+    pub command: TDCommand,
+}
+
+// State of the game a command was executed in
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordedState {
+    #[default]
+    Running,
+    Paused,
+    Over,
+}
+
+// Everything needed to reproduce a game: the seed and config it was
+// started with and every command that was executed during it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Replay {
+    pub seed: u64,
+    pub config: GameConfig,
+    pub commands: Vec<RecordedCommand>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, config: GameConfig) -> Self {
+        Self {
+            seed,
+            config,
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, &'static str> {
+        let replay_yaml =
+            std::fs::read_to_string(path).map_err(|_| "Unable to read replay file")?;
+        serde_yaml::from_str(&replay_yaml).map_err(|_| "Unable to deserialize replay")
+    }
+
+    pub fn export(&self, path: &str) -> Result<(), &'static str> {
+        let replay_as_str =
+            serde_yaml::to_string(&self).map_err(|_| "Unable to serialize replay")?;
+        std::fs::write(path, replay_as_str).map_err(|_| "Unable to write to file")?;
+
+        Ok(())
+    }
+
+    // the config to setup the game with, using the recorded seed
+    pub fn config(&self) -> GameConfig {
+        GameConfig {
+            seed: Some(self.seed),
+            ..self.config.clone()
+        }
+    }
+
+    // the recorded commands in the order they were executed, which is not
+    // sorted by tick once a load went back to an earlier one
+    pub fn into_queue(self) -> VecDeque<RecordedCommand> {
+        self.commands.into()
+    }
+}
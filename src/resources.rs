@@ -1,9 +1,14 @@
 use crate::utils::*;
-use crate::{communication::TDCommand, components::*, config::GameConfig};
+use crate::{
+    communication::{Requested, TDCommand},
+    components::*,
+    config::GameConfig,
+    replay::{RecordedCommand, Replay},
+};
 use bevy::prelude::*;
-use crossbeam_channel::Receiver;
-use rand::rngs::StdRng;
-use std::collections::HashMap;
+use crossbeam_channel::{Receiver, Sender};
+use rand_chacha::ChaCha12Rng;
+use std::collections::{HashMap, VecDeque};
 
 // Resources
 // #[derive(Debug, Resource)]
@@ -26,20 +31,61 @@ pub struct TileVisuals {
 pub struct EnemyVisuals {
     pub meshes: HashMap<MeshType, Handle<Mesh>>,
     pub materials: HashMap<MaterialType, Handle<ColorMaterial>>,
+    // by enemy type name
+    pub type_materials: HashMap<String, Handle<ColorMaterial>>,
+    // by status effect name
+    pub effect_materials: HashMap<String, Handle<ColorMaterial>>,
 }
 
 #[derive(Debug, Resource)]
 pub struct TowerVisuals {
     pub meshes: HashMap<MeshType, Handle<Mesh>>,
-    pub materials: HashMap<TowerType, Handle<ColorMaterial>>,
+    // by tower type name
+    pub materials: HashMap<String, Handle<ColorMaterial>>,
+    // for tower types without a material of their own
+    pub default_material: Handle<ColorMaterial>,
+    pub projectile_material: Handle<ColorMaterial>,
 }
 #[derive(Debug, Resource)]
-pub struct TDRng(pub StdRng);
+pub struct TDRng(pub ChaCha12Rng);
+
+// the seed `TDRng` was created from
+#[derive(Debug, Resource)]
+pub struct TDSeed(pub u64);
 
 #[derive(Debug, Resource)]
 pub struct GameCommandChannel(pub Receiver<TDCommand>);
 
+// used by in-game input (mouse, ui) to queue commands on the same
+// channel as outside commands so they are applied on a simulation tick
+#[derive(Debug, Resource)]
+pub struct GameCommandSender(pub Sender<TDCommand>);
+
+// commands of outside clients that wait for a response to them
+#[derive(Debug, Resource)]
+pub struct GameRequestChannel(pub Receiver<Requested<TDCommand>>);
+
+#[derive(Debug, Resource)]
+pub struct GameRequestSender(pub Sender<Requested<TDCommand>>);
+
+// number of fixed simulation ticks run while in game
 #[derive(Debug, Resource, Default)]
+pub struct SimulationTick(pub u64);
+
+#[derive(Debug, Resource)]
 pub struct SelectedTower {
     pub selected: TowerType,
 }
+
+// records executed commands into the replay file at `path`
+#[derive(Debug, Resource)]
+pub struct CommandRecorder {
+    pub path: String,
+    pub replay: Option<Replay>,
+}
+
+// commands of a replay that still have to be sent to the game
+#[derive(Debug, Resource)]
+pub struct ReplayPlayer {
+    pub commands: VecDeque<RecordedCommand>,
+}
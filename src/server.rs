@@ -0,0 +1,194 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use tracing::{event, Level};
+
+use crate::communication::{
+    parse_command, parse_request, CommandResponse, Reply, Requested, TDCommand,
+};
+
+// Listens on localhost for clients sending commands in the same line format as stdin,
+// or as json requests which are answered with a json response once carried out,
+// queries are answered with a json response in both formats,
+// every client is served on its own thread, returns the address listened on
+pub fn start_command_server(
+    port: u16,
+    tx: Sender<TDCommand>,
+    requests: Sender<Requested<TDCommand>>,
+) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let address = listener.local_addr()?;
+    event!(Level::INFO, "Listening for commands on {address}");
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let (tx, requests) = (tx.clone(), requests.clone());
+                    thread::spawn(move || serve_client(stream, tx, requests));
+                }
+                Err(e) => event!(Level::WARN, "Unable to accept command client: {e}"),
+            }
+        }
+    });
+    Ok(address)
+}
+
+// reads commands from a client until it disconnects or the game is gone
+fn serve_client(stream: TcpStream, tx: Sender<TDCommand>, requests: Sender<Requested<TDCommand>>) {
+    let peer = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+    event!(Level::INFO, "Command client {peer} connected");
+    let (reply_tx, reply_rx) = unbounded();
+    match stream.try_clone() {
+        Ok(writer) => {
+            thread::spawn(move || send_responses(writer, reply_rx));
+        }
+        Err(e) => event!(Level::WARN, "Unable to answer command client {peer}: {e}"),
+    }
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        event!(Level::DEBUG, "Read input from {peer}: {line}");
+        let line = line.trim();
+        if line.starts_with('{') {
+            let sent = match parse_request(line) {
+                Ok(request) => requests
+                    .send(Requested {
+                        command: request.command,
+                        reply: Some(Reply {
+                            id: Some(request.id),
+                            tx: reply_tx.clone(),
+                        }),
+                    })
+                    .is_ok(),
+                Err(response) => reply_tx.send(response).is_ok(),
+            };
+            if !sent {
+                break;
+            }
+            continue;
+        }
+        match parse_command(line) {
+            // queries are answered in the line format as well
+            Some(TDCommand::Query(query)) => {
+                let request = Requested {
+                    command: TDCommand::Query(query),
+                    reply: Some(Reply {
+                        id: None,
+                        tx: reply_tx.clone(),
+                    }),
+                };
+                if requests.send(request).is_err() {
+                    break;
+                }
+            }
+            Some(td_command) => {
+                if tx.send(td_command).is_err() {
+                    break;
+                }
+            }
+            None => event!(Level::DEBUG, "Unable to parse command from {peer}"),
+        }
+    }
+    event!(Level::INFO, "Command client {peer} disconnected");
+}
+
+// writes the responses to a client, one json object per line, until
+// the client is gone and no command of it is left in the game
+fn send_responses(mut writer: TcpStream, responses: Receiver<CommandResponse>) {
+    for response in responses {
+        let Ok(json) = serde_json::to_string(&response) else {
+            continue;
+        };
+        if writeln!(writer, "{json}").is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::{CommandError, TDQuery};
+    use std::time::Duration;
+
+    #[test]
+    fn clients_send_commands() {
+        let (tx, rx) = unbounded();
+        let (requests, _) = unbounded();
+        let address = start_command_server(0, tx, requests).unwrap();
+        let mut first = TcpStream::connect(address).unwrap();
+        let mut second = TcpStream::connect(address).unwrap();
+
+        first.write_all(b"toggle 1,2\nnonsense\n").unwrap();
+        let command = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(command, TDCommand::Toggle(t) if t.hex_pos.x == 1 && t.hex_pos.y == 2));
+        second.write_all(b"sell 3,4\n").unwrap();
+        let command = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(command, TDCommand::Sell(s) if s.hex_pos.x == 3 && s.hex_pos.y == 4));
+
+        // a client leaving does not stop the others
+        drop(first);
+        second.write_all(b"reset\n").unwrap();
+        let command = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(command, TDCommand::Restart(_)));
+    }
+
+    #[test]
+    fn json_requests_are_answered() {
+        let (tx, _) = unbounded();
+        let (requests, request_rx) = unbounded();
+        let address = start_command_server(0, tx, requests).unwrap();
+        let mut client = TcpStream::connect(address).unwrap();
+        let mut responses = BufReader::new(client.try_clone().unwrap()).lines();
+
+        client
+            .write_all(
+                b"{\"id\": 7, \"command\": {\"Sell\": {\"hex_pos\": {\"x\": 3, \"y\": 4}}}}\n",
+            )
+            .unwrap();
+        let request: Requested<TDCommand> =
+            request_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(
+            matches!(request.command, TDCommand::Sell(s) if s.hex_pos.x == 3 && s.hex_pos.y == 4)
+        );
+        // the game answers through the reply of the request
+        let reply = request.reply.unwrap();
+        reply
+            .tx
+            .send(CommandResponse {
+                id: reply.id,
+                error: Some(CommandError::NoTower(hexx::Hex::new(3, 4))),
+                data: None,
+            })
+            .unwrap();
+        assert_eq!(
+            responses.next().unwrap().unwrap(),
+            r#"{"id":7,"error":{"NoTower":{"x":3,"y":4}}}"#
+        );
+
+        // requests that can not be read are answered right away
+        client
+            .write_all(b"{\"id\": 8, \"command\": \"Fly\"}\n")
+            .unwrap();
+        let response: CommandResponse =
+            serde_json::from_str(&responses.next().unwrap().unwrap()).unwrap();
+        assert_eq!(response.id, Some(8));
+        assert!(matches!(
+            response.error,
+            Some(CommandError::InvalidRequest(_))
+        ));
+
+        // as are queries in the line format, without an id
+        client.write_all(b"paths\n").unwrap();
+        let request: Requested<TDCommand> =
+            request_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(request.command, TDCommand::Query(TDQuery::Paths)));
+        assert_eq!(request.reply.map(|reply| reply.id), Some(None));
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use bevy::prelude::{Timer, TimerMode, Vec3};
+use hexx::Hex;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{components::*, config::GameConfig};
+
+// bump whenever the snapshot format changes
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+// A full copy of a game in progress
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub tick: u64,
+    pub seed: u64,
+    pub rng: ChaCha12Rng,
+    pub config: GameConfig,
+    pub board: BoardSnapshot,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BoardSnapshot {
+    pub budget: u32,
+    pub player_score: u32,
+    pub enemy_score: u32,
+    // lives left on the goal
+    pub lives: u32,
+    pub game_timer: TimerSnapshot,
+    pub enemy_spawn_rate: TimerSnapshot,
+    pub tower_damaging_rate: TimerSnapshot,
+    pub game_over_timer: TimerSnapshot,
+    // how the game ended, for games saved after it was over
+    pub result: Option<Outcome>,
+    pub wave_state: WaveStateSnapshot,
+    pub paths: TDPaths,
+    // tick a path calculation running at the time is due on
+    pub paths_due: Option<u64>,
+    // sorted by their coordinates
+    pub tiles: Vec<TileSnapshot>,
+    pub enemies: Vec<EnemySnapshot>,
+    pub projectiles: Vec<ProjectileSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimerSnapshot {
+    pub duration: Duration,
+    pub elapsed: Duration,
+    pub repeating: bool,
+}
+
+impl From<&Timer> for TimerSnapshot {
+    fn from(timer: &Timer) -> Self {
+        Self {
+            duration: timer.duration(),
+            elapsed: timer.elapsed(),
+            repeating: timer.mode() == TimerMode::Repeating,
+        }
+    }
+}
+
+impl From<&TimerSnapshot> for Timer {
+    fn from(snapshot: &TimerSnapshot) -> Self {
+        let mode = if snapshot.repeating {
+            TimerMode::Repeating
+        } else {
+            TimerMode::Once
+        };
+        let mut timer = Timer::new(snapshot.duration, mode);
+        timer.set_elapsed(snapshot.elapsed);
+        timer
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WaveStateSnapshot {
+    pub wave: usize,
+    pub spawned: u32,
+    pub timer: TimerSnapshot,
+}
+
+impl From<&WaveState> for WaveStateSnapshot {
+    fn from(wave_state: &WaveState) -> Self {
+        Self {
+            wave: wave_state.wave,
+            spawned: wave_state.spawned,
+            timer: (&wave_state.timer).into(),
+        }
+    }
+}
+
+impl From<&WaveStateSnapshot> for WaveState {
+    fn from(snapshot: &WaveStateSnapshot) -> Self {
+        Self {
+            wave: snapshot.wave,
+            spawned: snapshot.spawned,
+            timer: (&snapshot.timer).into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TileSnapshot {
+    pub hex: Hex,
+    pub tile_type: TileType,
+    pub is_goal: bool,
+    pub is_spawn: bool,
+    pub on_path: bool,
+    pub tower: Option<Tower>,
+    pub tower_reload: Option<TimerSnapshot>,
+    pub tower_targeting: Option<TargetingMode>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnemySnapshot {
+    pub enemy: Enemy,
+    pub enemy_type: EnemyType,
+    pub status_effects: StatusEffects,
+    pub moves: Moves,
+    pub translation: Vec3,
+    pub scale: Vec3,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectileSnapshot {
+    // index of the target in `BoardSnapshot::enemies`
+    pub target: usize,
+    pub damage: u32,
+    pub damage_type: String,
+    pub effect: Option<String>,
+    pub speed: f32,
+    pub translation: Vec3,
+}
+
+#[derive(Debug, Deserialize)]
+struct SnapshotVersion {
+    version: u32,
+}
+
+impl Snapshot {
+    pub fn load(path: &str) -> Result<Self, &'static str> {
+        let snapshot_yaml =
+            std::fs::read_to_string(path).map_err(|_| "Unable to read snapshot file")?;
+        // check the version first, older formats would fail to deserialize
+        let version: SnapshotVersion =
+            serde_yaml::from_str(&snapshot_yaml).map_err(|_| "Unable to read snapshot version")?;
+        if version.version != SNAPSHOT_VERSION {
+            return Err("Unsupported snapshot version");
+        }
+        serde_yaml::from_str(&snapshot_yaml).map_err(|_| "Unable to deserialize snapshot")
+    }
+
+    pub fn export(&self, path: &str) -> Result<(), &'static str> {
+        let snapshot_as_str =
+            serde_yaml::to_string(&self).map_err(|_| "Unable to serialize snapshot")?;
+        std::fs::write(path, snapshot_as_str).map_err(|_| "Unable to write to file")?;
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use crate::communication::*;
-use crate::resources::GameCommandChannel;
+use crate::resources::{GameCommandChannel, GameRequestChannel};
 use crate::AppState;
 use crate::TDCommand;
 use bevy::prelude::*;
@@ -7,37 +7,63 @@ use tracing::event;
 use tracing::Level;
 
 pub fn event_dispatch(
-    mut command_send: EventWriter<TDCommand>,
+    mut command_send: EventWriter<Requested<TDCommand>>,
     channel: ResMut<GameCommandChannel>,
+    requests: Res<GameRequestChannel>,
 ) {
-    if channel.0.is_empty() {
-        return;
-    }
     while let Ok(ins) = channel.0.try_recv() {
         event!(Level::INFO, "Command sent (event_dispatch): {:#?}", ins);
-        command_send.send(ins);
+        command_send.send(Requested::new(ins));
+    }
+    // commands of clients waiting for a response
+    while let Ok(request) = requests.0.try_recv() {
+        event!(
+            Level::INFO,
+            "Command sent (event_dispatch): {:#?}",
+            request.command
+        );
+        command_send.send(request);
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn execute_outside_commands(
-    mut command_channel: EventReader<TDCommand>,
-    mut restart_channel: EventWriter<Restart>,
-    mut toggle_tiles: EventWriter<ToggleTile>,
-    mut create_tower: EventWriter<CreateTower>,
+    mut command_channel: EventReader<Requested<TDCommand>>,
+    mut restart_channel: EventWriter<Requested<Restart>>,
+    mut toggle_tiles: EventWriter<Requested<ToggleTile>>,
+    mut create_tower: EventWriter<Requested<CreateTower>>,
+    mut upgrade_tower: EventWriter<Requested<UpgradeTower>>,
+    mut sell_tower: EventWriter<Requested<SellTower>>,
+    mut set_targeting: EventWriter<Requested<SetTargeting>>,
+    mut save_game: EventWriter<Requested<SaveGame>>,
+    mut load_game: EventWriter<Requested<LoadGame>>,
+    mut queries: EventWriter<Requested<TDQuery>>,
+    mut results: EventWriter<CommandResult>,
+    state: Res<State<AppState>>,
 ) {
     if command_channel.is_empty() {
         return;
     }
-    for command in command_channel.iter() {
+    let mut requests = command_channel.iter();
+    while let Some(request) = requests.next() {
+        let command = &request.command;
         event!(Level::INFO, "Received command: {:#?}", command);
+        if state.0 != AppState::InGame && !command.outside_game() {
+            results.send(request.reject(CommandError::GameNotRunning));
+            continue;
+        }
         match command {
             TDCommand::Toggle(toggle) => {
                 event!(Level::INFO, "matched toggle tile on {:?}", toggle.hex_pos);
-                toggle_tiles.send(*toggle);
+                toggle_tiles.send(request.forward(*toggle));
             }
             TDCommand::Restart(_) => {
                 event!(Level::INFO, "matched reset, restarting...");
-                restart_channel.send(Restart);
+                restart_channel.send(request.forward(Restart));
+                // the commands after a restart are dropped with the board
+                for dropped in requests {
+                    results.send(dropped.reject(CommandError::Restarting));
+                }
                 return;
             }
             TDCommand::Tower(tower) => {
@@ -47,17 +73,72 @@ pub fn execute_outside_commands(
                     tower.tower_type,
                     tower.hex_pos
                 );
-                create_tower.send(*tower);
+                create_tower.send(request.forward(tower.clone()));
+            }
+            TDCommand::Upgrade(upgrade) => {
+                event!(
+                    Level::INFO,
+                    "matched upgrade tower on {:?}",
+                    upgrade.hex_pos
+                );
+                upgrade_tower.send(request.forward(*upgrade));
+            }
+            TDCommand::Sell(sell) => {
+                event!(Level::INFO, "matched sell tower on {:?}", sell.hex_pos);
+                sell_tower.send(request.forward(*sell));
+            }
+            TDCommand::Target(targeting) => {
+                event!(
+                    Level::INFO,
+                    "matched set targeting {:?} on {:?}",
+                    targeting.mode,
+                    targeting.hex_pos
+                );
+                set_targeting.send(request.forward(*targeting));
+            }
+            TDCommand::Save(save) => {
+                event!(Level::INFO, "matched save game to {}", save.path);
+                save_game.send(request.forward(save.clone()));
+            }
+            TDCommand::Load(load) => {
+                event!(Level::INFO, "matched load game from {}", load.path);
+                load_game.send(request.forward(load.clone()));
+            }
+            TDCommand::Query(query) => {
+                event!(Level::INFO, "matched query {:?}", query);
+                queries.send(request.forward(*query));
             }
         }
     }
 }
 
 pub fn receive_restart_command(
-    mut restart_reader: EventReader<Restart>,
+    mut restart_reader: EventReader<Requested<Restart>>,
+    mut results: EventWriter<CommandResult>,
     mut next_state: ResMut<NextState<AppState>>,
 ) {
-    if restart_reader.iter().last().is_some() {
+    for restart in restart_reader.iter() {
         next_state.set(AppState::Restart);
+        results.send(restart.accept());
+    }
+}
+
+// logs the commands that were refused and why,
+// and answers the clients waiting on them
+pub fn report_command_results(mut results: EventReader<CommandResult>) {
+    for CommandResult { reply, result } in results.iter() {
+        if let Err(error) = result {
+            event!(Level::WARN, "Command rejected: {error}");
+        }
+        if let Some(Reply { id, tx }) = reply {
+            let response = CommandResponse {
+                id: *id,
+                error: result.clone().err(),
+                data: None,
+            };
+            if tx.send(response).is_err() {
+                event!(Level::DEBUG, "Client of command {id:?} is gone");
+            }
+        }
     }
 }
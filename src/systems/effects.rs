@@ -0,0 +1,210 @@
+use crate::components::*;
+use crate::config::{Stacking, StatusEffectConfig};
+use crate::resources::*;
+use crate::systems::scaled_damage;
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+
+// puts the named effect on an enemy following the effect's stacking rule,
+// returns whether the effect is new to the enemy
+pub fn apply_status_effect(
+    effects: &mut StatusEffects,
+    name: &str,
+    status_effects: &BTreeMap<String, StatusEffectConfig>,
+) -> bool {
+    let Some(config) = status_effects.get(name) else {
+        return false;
+    };
+    let Some(active) = effects.0.iter_mut().find(|e| e.name == name) else {
+        effects.0.push(ActiveEffect {
+            name: name.to_string(),
+            stacks: 1,
+            remaining: config.duration,
+            next_tick: config.tick_interval,
+        });
+        return true;
+    };
+    match config.stacking {
+        Stacking::Refresh => active.remaining = config.duration,
+        Stacking::Extend => active.remaining += config.duration,
+        Stacking::Stack => {
+            active.stacks = (active.stacks + 1).min(config.max_stacks.max(1));
+            active.remaining = config.duration;
+        }
+    }
+    false
+}
+
+// how much the effects on an enemy scale its speed
+pub fn speed_multiplier(
+    effects: &StatusEffects,
+    status_effects: &BTreeMap<String, StatusEffectConfig>,
+) -> f32 {
+    effects
+        .0
+        .iter()
+        .filter_map(|e| {
+            let config = status_effects.get(&e.name)?;
+            Some(config.speed_multiplier.powi(e.stacks as i32))
+        })
+        .product()
+}
+
+// counts effects down, deals damage over time ignoring armor but not the
+// effectiveness of its damage type, and removes worn off effects,
+// enemies killed by effects are removed by `handle_enemy_damage`, the effects
+// only count as changed when one wears off so enemies are not tinted every tick
+pub fn tick_status_effects(
+    mut enemies: Query<(&mut Enemy, &EnemyType, &mut StatusEffects)>,
+    fixed_time: Res<FixedTime>,
+    config: Res<Config>,
+) {
+    let period = fixed_time.period.as_secs_f32();
+    for (mut enemy, enemy_type, mut effects) in enemies.iter_mut() {
+        if effects.0.is_empty() {
+            continue;
+        }
+        let active = effects.bypass_change_detection();
+        for effect in active.0.iter_mut() {
+            effect.remaining -= period;
+            let Some(effect_config) = config.0.status_effects.get(&effect.name) else {
+                continue;
+            };
+            if effect_config.damage_per_tick == 0 {
+                continue;
+            }
+            effect.next_tick -= period;
+            while effect.next_tick <= 0. {
+                let damage = scaled_damage(
+                    &config.0,
+                    enemy_type,
+                    effect_config.damage_per_tick * effect.stacks,
+                    &effect_config.damage_type,
+                );
+                enemy.health = enemy.health.saturating_sub(damage);
+                effect.next_tick += effect_config.tick_interval.max(period);
+            }
+        }
+        let count = active.0.len();
+        active.0.retain(|e| e.remaining > 0.);
+        if active.0.len() != count {
+            effects.set_changed();
+        }
+    }
+}
+
+// colors enemies by their latest status effect, or by their type without effects
+pub fn tint_enemies(
+    mut commands: Commands,
+    enemies: Query<(Entity, &EnemyType, &StatusEffects), Changed<StatusEffects>>,
+    enemy_visuals: Res<EnemyVisuals>,
+) {
+    for (entity, enemy_type, effects) in enemies.iter() {
+        let material = effects
+            .0
+            .last()
+            .and_then(|e| enemy_visuals.effect_materials.get(&e.name))
+            .or_else(|| enemy_visuals.type_materials.get(&enemy_type.0));
+        if let Some(material) = material {
+            if let Some(mut entity_commands) = commands.get_entity(entity) {
+                entity_commands.insert(material.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+
+    #[test]
+    fn effects_follow_stacking_rules() {
+        let status_effects = GameConfig::default().status_effects;
+        let mut effects = StatusEffects::default();
+        for _ in 0..10 {
+            apply_status_effect(&mut effects, "slow", &status_effects);
+            apply_status_effect(&mut effects, "poison", &status_effects);
+            apply_status_effect(&mut effects, "burn", &status_effects);
+        }
+        apply_status_effect(&mut effects, "unknown", &status_effects);
+
+        let effect = |name| effects.0.iter().find(|e| e.name == name).unwrap();
+        // refreshed
+        assert_eq!(effect("slow").stacks, 1);
+        assert_eq!(effect("slow").remaining, status_effects["slow"].duration);
+        // stacked up to the limit
+        assert_eq!(effect("poison").stacks, status_effects["poison"].max_stacks);
+        // extended
+        assert_eq!(
+            effect("burn").remaining,
+            status_effects["burn"].duration * 10.
+        );
+        assert_eq!(effects.0.len(), 3);
+    }
+
+    #[test]
+    fn slows_and_stuns_scale_speed() {
+        let status_effects = GameConfig::default().status_effects;
+        let mut effects = StatusEffects::default();
+        assert_eq!(speed_multiplier(&effects, &status_effects), 1.);
+        apply_status_effect(&mut effects, "slow", &status_effects);
+        assert_eq!(
+            speed_multiplier(&effects, &status_effects),
+            status_effects["slow"].speed_multiplier
+        );
+        apply_status_effect(&mut effects, "freeze", &status_effects);
+        assert_eq!(speed_multiplier(&effects, &status_effects), 0.);
+    }
+
+    #[derive(Resource, Default)]
+    struct Changes(usize);
+
+    fn count_changes(mut changes: ResMut<Changes>, effects: Query<(), Changed<StatusEffects>>) {
+        changes.0 += effects.iter().count();
+    }
+
+    #[test]
+    fn effects_only_change_when_one_wears_off() {
+        let config = GameConfig::default();
+        let mut effects = StatusEffects::default();
+        assert!(apply_status_effect(
+            &mut effects,
+            "slow",
+            &config.status_effects
+        ));
+        assert!(!apply_status_effect(
+            &mut effects,
+            "slow",
+            &config.status_effects
+        ));
+        let ticks = (config.status_effects["slow"].duration / 0.1).round() as usize;
+
+        let mut world = World::new();
+        world.insert_resource(FixedTime::new_from_secs(0.1));
+        world.insert_resource(Config(config));
+        world.init_resource::<Changes>();
+        let enemy = Enemy {
+            health: 10,
+            max_health: 10,
+            value: 1,
+        };
+        world.spawn((enemy, EnemyType("runner".to_string()), effects));
+        let mut schedule = Schedule::new();
+        schedule.add_systems((tick_status_effects, count_changes).chain());
+
+        // spawning the enemy counts as a change
+        schedule.run(&mut world);
+        assert_eq!(world.resource::<Changes>().0, 1);
+        for _ in 1..ticks - 1 {
+            schedule.run(&mut world);
+        }
+        assert_eq!(world.resource::<Changes>().0, 1);
+        for _ in 0..2 {
+            schedule.run(&mut world);
+        }
+        assert_eq!(world.resource::<Changes>().0, 2);
+        let mut effects = world.query::<&StatusEffects>();
+        assert!(effects.single(&world).0.is_empty());
+    }
+}
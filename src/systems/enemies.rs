@@ -1,251 +1,820 @@
 use crate::{
     communication::{RecalculateEnemyPaths, RefreshTowerDamage},
     components::*,
-    config::EnemyConfig,
+    config::{GameConfig, WaveConfig, WaveEnemies},
+    flow_field::{step_cost, FlowField},
     resources::*,
+    systems::{apply_status_effect, speed_multiplier},
     utils::*,
 };
 use bevy::prelude::*;
 use bevy::sprite::MaterialMesh2dBundle;
-use hexx::{algorithms::*, Hex};
-use rand::prelude::*;
-use std::collections::HashMap;
+use bevy::tasks::AsyncComputeTaskPool;
+use futures_lite::future;
+use hexx::Hex;
+use rand::{distributions::WeightedIndex, prelude::*};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
 use tracing::{event, Level};
 
+// on every damaging tick enemies regenerate, then take the damage and status
+// effects of the damaging tile they are on, they die without health
+#[allow(clippy::too_many_arguments)]
 pub fn handle_enemy_damage(
-    mut enemies: Query<(Entity, &mut Enemy, &Transform)>,
-    tiles: Query<Option<&Damaging>>,
+    mut enemies: Query<(
+        Entity,
+        &mut Enemy,
+        &EnemyType,
+        &mut StatusEffects,
+        &Transform,
+    )>,
+    tiles: Query<&Damaging>,
     grid: Query<&HexGrid>,
     mut budget: Query<(&mut Budget, &mut ScoreBoard)>,
     mut timers: Query<&mut TDTimers>,
     mut commands: Commands,
-    time: Res<Time>,
+    fixed_time: Res<FixedTime>,
+    config: Res<Config>,
 ) {
-    let mut timers = timers.single_mut();
-    if timers
+    let damaging_tick = timers
+        .single_mut()
         .tower_damaging_rate
-        .tick(time.delta())
-        .just_finished()
-    {
-        let grid = grid.single();
-        for (entity, mut enemy, transform) in enemies.iter_mut() {
-            let enemy_hex_pos = grid
-                .layout
-                .world_pos_to_hex(transform.translation.truncate());
-            if let Some(tile_entity) = grid.entities.get(&enemy_hex_pos) {
-                let s = tiles.get(*tile_entity);
-                match s {
-                    Ok(Some(dmg)) => {
-                        enemy.health = enemy.health.saturating_sub(dmg.value);
+        .tick(fixed_time.period)
+        .just_finished();
+    let grid = grid.single();
+    for (entity, mut enemy, enemy_type, mut effects, transform) in enemies.iter_mut() {
+        let enemy_hex_pos = grid
+            .layout
+            .world_pos_to_hex(transform.translation.truncate());
+        // enemies hit by projectiles this tick are already dead
+        if damaging_tick && enemy.health > 0 {
+            let regeneration = config
+                .0
+                .enemy_config
+                .enemy_types
+                .get(&enemy_type.0)
+                .map_or(0, |t| t.regeneration);
+            enemy.health = (enemy.health + regeneration).min(enemy.max_health);
+            let damaging = grid
+                .entities
+                .get(&enemy_hex_pos)
+                .and_then(|tile| tiles.get(*tile).ok());
+            if let Some(damaging) = damaging {
+                for (damage_type, damage) in damaging.damage.iter() {
+                    let damage = hit_damage(&config.0, enemy_type, *damage, damage_type);
+                    enemy.health = enemy.health.saturating_sub(damage);
+                }
+                for effect in damaging.effects.keys() {
+                    if apply_status_effect(
+                        effects.bypass_change_detection(),
+                        effect,
+                        &config.0.status_effects,
+                    ) {
+                        effects.set_changed();
                     }
-                    _ => continue,
                 }
             }
-            if enemy.health == 0 {
-                if let Some(mut entity_commands) = commands.get_entity(entity) {
-                    entity_commands.despawn();
-                }
-                let (mut budget, mut score_board) = budget.single_mut();
-                budget.0 += enemy.value;
-                // add player score
-                score_board.player_score += 1;
-                event!(
-                    Level::INFO,
-                    "Enemy died at {:?} Earned: {}",
-                    enemy_hex_pos,
-                    enemy.value
-                );
+        }
+        if enemy.health == 0 {
+            if let Some(mut entity_commands) = commands.get_entity(entity) {
+                entity_commands.despawn();
             }
+            let (mut budget, mut score_board) = budget.single_mut();
+            budget.0 += enemy.value;
+            // add player score
+            score_board.player_score += 1;
+            event!(
+                Level::INFO,
+                "Enemy died at {:?} Earned: {}",
+                enemy_hex_pos,
+                enemy.value
+            );
         }
     }
 }
 
+// damage of a hit on an enemy, scaled by how well the damage type works against
+// the enemy's armor class, then reduced by its armor, a damaging hit always deals
+// at least 1 damage unless the enemy is immune to the damage type
+pub fn hit_damage(
+    config: &GameConfig,
+    enemy_type: &EnemyType,
+    damage: u32,
+    damage_type: &str,
+) -> u32 {
+    let Some(enemy_config) = config.enemy_config.enemy_types.get(&enemy_type.0) else {
+        return damage;
+    };
+    let damage = scaled_damage(config, enemy_type, damage, damage_type);
+    if damage == 0 {
+        return 0;
+    }
+    damage.saturating_sub(enemy_config.armor).max(1)
+}
+
+// damage scaled by how well the damage type works against the enemy's armor class
+pub fn scaled_damage(
+    config: &GameConfig,
+    enemy_type: &EnemyType,
+    damage: u32,
+    damage_type: &str,
+) -> u32 {
+    let Some(enemy_config) = config.enemy_config.enemy_types.get(&enemy_type.0) else {
+        return damage;
+    };
+    let multiplier = config.effectiveness(damage_type, &enemy_config.armor_class);
+    if damage == 0 || multiplier <= 0. {
+        return 0;
+    }
+    ((damage as f32 * multiplier).round() as u32).max(1)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn handle_enemy_movement(
-    time: Res<Time>,
-    path_tiles: Query<Entity, With<OnPath>>,
+    fixed_time: Res<FixedTime>,
     grid: Query<&HexGrid>,
     paths: Query<&TDPaths>,
     config: Res<Config>,
     mut commands: Commands,
-    mut enemies: Query<(Entity, &mut Moves, &mut Transform), With<Enemy>>,
+    mut enemies: Query<
+        (
+            Entity,
+            &mut Moves,
+            &EnemyType,
+            &StatusEffects,
+            &mut Transform,
+        ),
+        With<Enemy>,
+    >,
     mut score_board: Query<&mut ScoreBoard>,
+    mut goal: Query<&mut Lives, With<IsGoal>>,
+    tiles: Query<(&Tile, Option<&HasTower>)>,
 ) {
     let mut score_board = score_board.single_mut();
+    let mut lives = goal.single_mut();
     let grid = grid.single();
-    for (entity, mut moves, mut transform) in enemies.iter_mut() {
-        let curr_world_pos = Vec2::from((transform.translation.x, transform.translation.y));
-        let curr_hex_pos = grid.layout.world_pos_to_hex(curr_world_pos);
-        // if enemy still exists on tile that is no longer on path, despawn
-        if path_tiles
-            .get(*grid.entities.get(&curr_hex_pos).unwrap())
-            .is_err()
-            && curr_hex_pos != Hex::ZERO
-        {
+    let td_paths = paths.single();
+    // tiles blocked since the flow field was calculated
+    let is_blocked = |hex: Hex| {
+        grid.entities
+            .get(&hex)
+            .and_then(|entity| tiles.get(*entity).ok())
+            .is_some_and(|(tile, has_tower)| {
+                step_cost(tile.tile_type, has_tower.is_some()).is_none()
+            })
+    };
+    for (entity, mut moves, enemy_type, effects, mut transform) in enemies.iter_mut() {
+        if moves.from == td_paths.goal {
+            // destroy enemies and damage the goal
             if let Some(mut entity_commands) = commands.get_entity(entity) {
                 entity_commands.despawn();
+                let goal_damage = config
+                    .0
+                    .enemy_config
+                    .enemy_types
+                    .get(&enemy_type.0)
+                    .map_or(1, |t| t.goal_damage);
+                score_board.enemy_score += goal_damage;
+                lives.0 = lives.0.saturating_sub(goal_damage);
+                event!(Level::DEBUG, "Enemy reached goal and despawned");
             }
+            continue;
         }
-
-        let td_paths = paths.single();
-        if let Some(path) = td_paths.paths.as_ref().unwrap().get(&moves.path_index.0) {
-            // if index is the last in path
-            if moves.path_index.1 == path.len() {
-                // destroy enemies and reduce score
-                if let Some(mut entity_commands) = commands.get_entity(entity) {
-                    entity_commands.despawn();
-                    score_board.enemy_score += 1;
-                    event!(Level::DEBUG, "Enemy reached goal and despawned");
-                    continue;
-                }
-            }
-            if let Some(new_hex_pos) = path.get(moves.path_index.1 + 1) {
-                moves.lerp += moves.speed * config.0.enemy_config.base_speed * time.delta_seconds();
-                if moves.lerp > 1. {
-                    moves.path_index.1 += 1;
-                    moves.lerp -= 1.;
-                }
-                let new_world_pos = grid.layout.hex_to_world_pos(*new_hex_pos);
-                transform.translation = curr_world_pos
-                    .lerp(new_world_pos, moves.lerp)
-                    .extend(transform.translation.z);
-            } else {
-                // this means that they got stuck on the border
-                // TODO: figure out why
-                moves.path_index.1 += 1;
-            }
+        let step = moves.speed
+            * speed_multiplier(effects, &config.0.status_effects)
+            * config.0.enemy_config.base_speed
+            * fixed_time.period.as_secs_f32();
+        // enemies heading onto a blocked tile wait at its border until
+        // `apply_enemy_paths` turns them around
+        if is_blocked(moves.to) && !is_blocked(moves.from) {
+            moves.lerp = (moves.lerp + step).min(moves.lerp.max(0.5));
+        } else {
+            moves.lerp += step;
         }
+        if moves.lerp >= 1. {
+            // arrived, the flow field tells where to go from here,
+            // enemies wait on the tile while the next one is blocked
+            moves.lerp -= 1.;
+            moves.from = moves.to;
+            moves.to = td_paths
+                .flow_field
+                .next_step(moves.from)
+                .filter(|next| !is_blocked(*next))
+                .unwrap_or(moves.from);
+        }
+        let from = grid.layout.hex_to_world_pos(moves.from);
+        let to = grid.layout.hex_to_world_pos(moves.to);
+        transform.translation = from.lerp(to, moves.lerp).extend(transform.translation.z);
     }
 }
 
-// TODO: parallelize
+// starts calculating the paths on request on the async compute pool, they are applied
+// `path_calculation_ticks` later so the game plays out the same however long it takes
 pub fn recalculate_enemy_path(
     mut commands: Commands,
     tiles: Query<(&Tile, Option<&HasTower>)>,
-    mut paths: Query<&mut TDPaths>,
-    on_path: Query<(Entity, &Coords), With<OnPath>>,
-    grid: Query<&HexGrid>,
+    board: Query<(Entity, &TDPaths, &HexGrid)>,
     mut recalculate_enemy_paths: EventReader<RecalculateEnemyPaths>,
-    mut place_tower_damage: EventWriter<RefreshTowerDamage>,
+    tick: Res<SimulationTick>,
+    config: Res<Config>,
 ) {
-    if recalculate_enemy_paths.iter().last().is_some() {
-        event!(
-            Level::INFO,
-            "Received recalculate path command, removing OnPath components"
-        );
-        for (entity, coords) in on_path.iter() {
-            if let Some(mut entity_commands) = commands.get_entity(entity) {
-                event!(Level::DEBUG, "Removed OnPath at {:?}", coords.0);
-                entity_commands.remove::<OnPath>();
-            }
-        }
-        let grid = grid.single();
-        event!(Level::INFO, "Calculating enemy path");
-        let mut td_paths = paths.single_mut();
-        // recalculate paths
-        let new_paths: HashMap<usize, Vec<Hex>> = td_paths
-            .spawns
+    if recalculate_enemy_paths.iter().last().is_none() {
+        return;
+    }
+    event!(Level::INFO, "Calculating enemy path");
+    let (board, td_paths, grid) = board.single();
+    let step_costs: HashMap<Hex, u32> = grid
+        .entities
+        .iter()
+        .filter_map(|(hex, entity)| {
+            let (tile, has_tower) = tiles.get(*entity).expect("Could not find entity in query");
+            Some((*hex, step_cost(tile.tile_type, has_tower.is_some())?))
+        })
+        .collect();
+    // without paths to keep using until then there is no point in waiting
+    let delay = match td_paths.paths {
+        Some(_) => config.0.path_calculation_ticks as u64,
+        None => 0,
+    };
+    // replaces, and so cancels, a calculation still running
+    commands.entity(board).insert(calculate_paths(
+        td_paths.goal,
+        td_paths.spawns.clone(),
+        step_costs,
+        tick.0 + delay,
+        tick.0,
+    ));
+}
+
+// calculates the flow field and the way from every spawn along it, in the
+// background unless the paths are due on the current `tick` already
+pub fn calculate_paths(
+    goal: Hex,
+    spawns: Vec<Hex>,
+    step_costs: HashMap<Hex, u32>,
+    due: u64,
+    tick: u64,
+) -> PendingPaths {
+    let calculate = move || {
+        let flow_field = FlowField::new(goal, |hex| step_costs.get(&hex).copied());
+        let paths = spawns
             .iter()
             .enumerate()
-            .map(|(i, spawn)| {
-                let path: Vec<Hex> = a_star(*spawn, Hex::ZERO, |hex| {
-                    if grid.entities.contains_key(&hex) {
-                        let (tile, has_tower) = tiles
-                            .get(
-                                *grid
-                                    .entities
-                                    .get(&hex)
-                                    .expect("Cannot find corresponding entity to hex"),
-                            )
-                            .expect("Could not find entity in query");
-                        if has_tower.is_some() {
-                            Some(1000)
-                        } else {
-                            match tile.tile_type {
-                                TileType::Plains => Some(0),
-                                TileType::Mountain => Some(1000),
-                            }
-                        }
-                    } else {
-                        None
-                    }
-                })
-                .unwrap();
-                path.iter().for_each(|hex| {
-                    if *hex != Hex::ZERO {
-                        if let Some(entity) = grid.entities.get(hex) {
-                            if let Some(mut entity_commands) = commands.get_entity(*entity) {
-                                entity_commands.insert(OnPath);
-                            }
-                        }
-                    }
-                });
-                (i, path)
-            })
+            .filter_map(|(i, spawn)| Some((i, flow_field.path(*spawn)?)))
             .collect();
-        td_paths.paths = Some(new_paths);
-        recalculate_enemy_paths.clear();
-        place_tower_damage.send(RefreshTowerDamage);
+        CalculatedPaths { flow_field, paths }
+    };
+    let calculation = if due <= tick {
+        PathCalculation::Done(calculate())
+    } else {
+        PathCalculation::Running(AsyncComputeTaskPool::get().spawn(async move { calculate() }))
+    };
+    PendingPaths { calculation, due }
+}
+
+// whether the next tick can run, it waits for paths due on it that are still being
+// calculated instead of blocking on them, so that they apply on the tick they are due
+pub fn paths_are_ready(board: Query<&PendingPaths>, tick: Res<SimulationTick>) -> bool {
+    board
+        .get_single()
+        .map_or(true, |pending| match &pending.calculation {
+            PathCalculation::Running(task) => tick.0 + 1 < pending.due || task.is_finished(),
+            PathCalculation::Done(_) => true,
+        })
+}
+
+// swaps in calculated paths once they are due, marking the tiles on the new paths
+#[allow(clippy::type_complexity)]
+pub fn apply_enemy_paths(
+    mut commands: Commands,
+    mut board: Query<(Entity, &mut TDPaths, &HexGrid, &mut PendingPaths)>,
+    on_path: Query<(Entity, &Coords), With<OnPath>>,
+    mut enemies: Query<&mut Moves>,
+    mut place_tower_damage: EventWriter<RefreshTowerDamage>,
+    tick: Res<SimulationTick>,
+) {
+    let Ok((board, mut td_paths, grid, mut pending)) = board.get_single_mut() else {
+        return;
+    };
+    if tick.0 < pending.due {
+        return;
+    }
+    let calculated = match &mut pending.calculation {
+        // finished, the tick waited for `paths_are_ready`
+        PathCalculation::Running(task) => future::block_on(future::poll_once(task)),
+        PathCalculation::Done(calculated) => Some(std::mem::take(calculated)),
+    };
+    let Some(CalculatedPaths { flow_field, paths }) = calculated else {
+        return;
+    };
+    commands.entity(board).remove::<PendingPaths>();
+    event!(
+        Level::INFO,
+        "Applying enemy paths, removing OnPath components"
+    );
+    for (entity, coords) in on_path.iter() {
+        if let Some(mut entity_commands) = commands.get_entity(entity) {
+            event!(Level::DEBUG, "Removed OnPath at {:?}", coords.0);
+            entity_commands.remove::<OnPath>();
+        }
+    }
+    for (i, spawn) in td_paths.spawns.iter().enumerate() {
+        let Some(path) = paths.get(&i) else {
+            event!(Level::WARN, "No path from spawn {spawn:?}");
+            continue;
+        };
+        for hex in path.iter().filter(|hex| **hex != flow_field.goal) {
+            if let Some(mut entity_commands) = grid
+                .entities
+                .get(hex)
+                .and_then(|entity| commands.get_entity(*entity))
+            {
+                entity_commands.insert(OnPath);
+            }
+        }
+    }
+    // enemies heading onto a tile that got blocked turn back, all
+    // others finish their step and follow the new field from there
+    for mut moves in enemies.iter_mut() {
+        if flow_field.cost(moves.to).is_none() && flow_field.cost(moves.from).is_some() {
+            (moves.from, moves.to) = (moves.to, moves.from);
+            moves.lerp = 1. - moves.lerp;
+        }
     }
+    td_paths.flow_field = flow_field;
+    td_paths.paths = Some(paths);
+    place_tower_damage.send(RefreshTowerDamage);
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_enemies(
     mut commands: Commands,
-    time: Res<Time>,
-    board: Query<Entity, With<TDBoard>>,
-    paths: Query<&mut TDPaths>,
+    fixed_time: Res<FixedTime>,
+    mut board: Query<(Entity, &mut TDTimers, &mut WaveState), With<TDBoard>>,
+    paths: Query<&TDPaths>,
     grid: Query<&HexGrid>,
     config: Res<Config>,
-    mut timers: Query<&mut TDTimers>,
     enemy_visuals: Res<EnemyVisuals>,
     mut rng: ResMut<TDRng>,
 ) {
-    let mut timers = timers.single_mut();
+    let (board_entity, mut timers, mut wave_state) = board.single_mut();
+    let td_paths = paths.single();
+    let Some(paths) = td_paths.paths.as_ref() else {
+        return;
+    };
+    let enemy_types = &config.0.enemy_config.enemy_types;
+    let spawn = if config.0.waves.is_empty() {
+        // no scripted waves, spawn endlessly from random spawns
+        if !timers
+            .enemy_spawn_rate
+            .tick(fixed_time.period)
+            .just_finished()
+        {
+            return;
+        }
+        let Some(spawn) = paths.keys().choose(&mut rng.0).copied() else {
+            return;
+        };
+        let Ok(weights) = WeightedIndex::new(enemy_types.values().map(|t| t.spawn_weight)) else {
+            event!(Level::WARN, "No enemy type with a spawn weight");
+            return;
+        };
+        let name = enemy_types.keys().nth(weights.sample(&mut rng.0)).unwrap();
+        Some((spawn, name.clone()))
+    } else {
+        next_wave_enemy(&mut wave_state, &config.0.waves, fixed_time.period, paths).map(
+            |(spawns, enemies)| {
+                let spawn = *spawns.choose(&mut rng.0).unwrap();
+                (spawn, enemies.enemy_type.clone())
+            },
+        )
+    };
+    let Some((spawn, name)) = spawn else {
+        return;
+    };
+    if !paths.contains_key(&spawn) {
+        event!(Level::WARN, "No path from spawn {spawn}");
+        return;
+    }
+    let Some(enemy_type) = enemy_types.get(&name) else {
+        event!(Level::WARN, "Unknown enemy type {name}");
+        return;
+    };
+
     let grid = grid.single();
-    if timers.enemy_spawn_rate.tick(time.delta()).just_finished() {
-        let board_entity = board.single();
-        let paths = paths.single();
-        if let Some(paths) = paths.paths.as_ref() {
-            let path = paths.iter().choose(&mut rng.0).unwrap();
-            let spawn_location = path.1.first().unwrap();
-            let Vec2 { x, y } = grid.layout.hex_to_world_pos(*spawn_location);
-            let EnemyConfig {
-                base_speed,
-                min_max_health,
-                ..
-            } = config.0.enemy_config;
-
-            let health: u32 = rng.0.gen_range(min_max_health.0..=min_max_health.1);
-            let scale = health as f32 / (min_max_health.1 - min_max_health.0) as f32;
-            let speed = (1.0 - (health as f32 / (min_max_health.1 - min_max_health.0) as f32))
-                .clamp(0.1, 1.)
-                * base_speed;
-            let value = health;
-            commands
-                .spawn((
-                    MaterialMesh2dBundle {
-                        mesh: enemy_visuals
-                            .meshes
-                            .get(&MeshType::Enemy)
-                            .unwrap()
-                            .clone()
-                            .into(),
-                        material: enemy_visuals
-                            .materials
-                            .get(&MaterialType::Enemy)
-                            .unwrap()
-                            .clone(),
-                        transform: Transform::from_translation(Vec3::from((x, y, 5.)))
-                            .with_scale(Vec3::splat(scale)),
-                        ..default()
-                    },
-                    Enemy { health, value },
-                    Moves {
-                        lerp: 0.,
-                        path_index: (*path.0, 0),
-                        speed,
-                    },
-                ))
-                .set_parent(board_entity);
+    let spawn_location = td_paths.spawns[spawn];
+    let Vec2 { x, y } = grid.layout.hex_to_world_pos(spawn_location);
+    let (min_health, max_health) = enemy_type.min_max_health;
+    let health: u32 = rng.0.gen_range(min_health..=max_health);
+    let enemy_type_component = EnemyType(name);
+    commands
+        .spawn((
+            enemy_bundle(
+                &enemy_visuals,
+                &enemy_type_component,
+                Transform::from_translation(Vec3::from((x, y, 5.)))
+                    .with_scale(Vec3::splat(enemy_type.size)),
+            ),
+            Enemy {
+                health,
+                max_health: health,
+                value: enemy_type.reward,
+            },
+            enemy_type_component,
+            StatusEffects::default(),
+            Moves {
+                from: spawn_location,
+                to: td_paths
+                    .flow_field
+                    .next_step(spawn_location)
+                    .unwrap_or(spawn_location),
+                lerp: 0.,
+                speed: enemy_type.speed,
+            },
+        ))
+        .set_parent(board_entity);
+}
+
+// advances the scripted waves, returning the spawns with a path and the enemies of the
+// next enemy due, which stays due while none of its spawns has a path
+fn next_wave_enemy<'a>(
+    wave_state: &mut WaveState,
+    waves: &'a [WaveConfig],
+    period: Duration,
+    paths: &BTreeMap<usize, Vec<Hex>>,
+) -> Option<(Vec<usize>, &'a WaveEnemies)> {
+    if !wave_state.timer.tick(period).finished() {
+        return None;
+    }
+    let mut wave = waves.get(wave_state.wave)?;
+    if wave_state.spawned >= wave.enemy_count() {
+        // the delay after the wave is over, start the next one
+        wave_state.wave += 1;
+        wave_state.spawned = 0;
+        wave = waves.get(wave_state.wave)?;
+    }
+    let spawns: Vec<usize> = if wave.spawns.is_empty() {
+        paths.keys().copied().collect()
+    } else {
+        wave.spawns
+            .iter()
+            .copied()
+            .filter(|spawn| paths.contains_key(spawn))
+            .collect()
+    };
+    if spawns.is_empty() {
+        return None;
+    }
+    let enemies = wave.enemies_at(wave_state.spawned);
+    wave_state.spawned += 1;
+    let wait = if wave_state.spawned >= wave.enemy_count() {
+        wave.next_wave_delay
+    } else {
+        wave.spawn_interval
+    };
+    wave_state.timer = Timer::from_seconds(wait, TimerMode::Once);
+    enemies.map(|enemies| (spawns, enemies))
+}
+
+// visuals of an enemy at `transform`
+pub fn enemy_bundle(
+    enemy_visuals: &EnemyVisuals,
+    enemy_type: &EnemyType,
+    transform: Transform,
+) -> MaterialMesh2dBundle<ColorMaterial> {
+    MaterialMesh2dBundle {
+        mesh: enemy_visuals
+            .meshes
+            .get(&MeshType::Enemy)
+            .unwrap()
+            .clone()
+            .into(),
+        material: enemy_visuals
+            .type_materials
+            .get(&enemy_type.0)
+            .or_else(|| enemy_visuals.materials.get(&MaterialType::Enemy))
+            .unwrap()
+            .clone(),
+        transform,
+        ..default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::parse_command;
+    use crate::communication::CommandResult;
+    use crate::config;
+    use crate::setup_tower_defense_headless;
+    use crate::test_utils::*;
+    use bevy::time::TimeUpdateStrategy;
+    use std::time::Instant;
+
+    #[test]
+    fn damage_types_scale_by_armor_class() {
+        let config = GameConfig::default();
+        let tank = EnemyType("tank".to_string());
+        let armor = config.enemy_config.enemy_types["tank"].armor;
+        // heavy armor shrugs off physical damage and is weak to magic
+        assert_eq!(hit_damage(&config, &tank, 10, "physical"), 5 - armor);
+        assert_eq!(hit_damage(&config, &tank, 10, "magic"), 15 - armor);
+        // missing entries are neutral
+        assert_eq!(hit_damage(&config, &tank, 10, "cold"), 10 - armor);
+        assert_eq!(hit_damage(&config, &tank, 0, "magic"), 0);
+
+        let mut config = config;
+        config.damage_effectiveness.insert(
+            "holy".to_string(),
+            BTreeMap::from([("heavy".to_string(), 0.)]),
+        );
+        assert_eq!(hit_damage(&config, &tank, 10, "holy"), 0);
+    }
+
+    #[test]
+    fn waves_spawn_in_order() {
+        let wave = |counts: &[u32]| config::WaveConfig {
+            enemies: counts
+                .iter()
+                .map(|&count| config::WaveEnemies {
+                    count,
+                    enemy_type: "runner".to_string(),
+                })
+                .collect(),
+            spawn_interval: 0.1,
+            next_wave_delay: 0.5,
+            spawns: vec![0],
+        };
+        let config = GameConfig {
+            seed: Some(42),
+            waves: vec![wave(&[2, 1]), wave(&[2])],
+            ..default()
+        };
+        let (mut app, _tx) = setup_tower_defense_headless(config);
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        let frame_length = tick_length(&app);
+        let mut wave_state = app.world.query::<&WaveState>();
+        let mut enemies = app.world.query::<&Moves>();
+
+        // first wave fully spawned, waiting for the second
+        run_until_tick(&mut app, 30, frame_length);
+        let state = wave_state.single(&app.world);
+        assert_eq!((state.wave, state.spawned), (0, 3));
+        assert_eq!(enemies.iter(&app.world).count(), 3);
+        let path = &app
+            .world
+            .query::<&TDPaths>()
+            .single(&app.world)
+            .paths
+            .as_ref()
+            .unwrap()[&0];
+        assert!(enemies.iter(&app.world).all(|m| path.contains(&m.to)));
+
+        run_until_tick(&mut app, 50, frame_length);
+        let state = wave_state.single(&app.world);
+        assert_eq!((state.wave, state.spawned), (1, 1));
+
+        // no enemies after the last wave
+        run_until_tick(&mut app, 100, frame_length);
+        assert_eq!(wave_state.single(&app.world).wave, 2);
+        run_until_tick(&mut app, 200, frame_length);
+        let spawned = enemies.iter(&app.world).count() as u32
+            + app
+                .world
+                .query::<&ScoreBoard>()
+                .single(&app.world)
+                .enemy_score;
+        assert_eq!(spawned, 5);
+    }
+
+    #[test]
+    fn wave_enemies_wait_for_a_path_from_their_spawn() {
+        let config = GameConfig {
+            seed: Some(42),
+            waves: vec![config::WaveConfig {
+                enemies: vec![config::WaveEnemies {
+                    count: 1,
+                    enemy_type: "runner".to_string(),
+                }],
+                spawn_interval: 0.1,
+                next_wave_delay: 0.5,
+                spawns: vec![0],
+            }],
+            ..default()
+        };
+        let (mut app, _tx) = setup_tower_defense_headless(config);
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        let mut td_paths = app.world.query::<&mut TDPaths>();
+        let path = td_paths
+            .single_mut(&mut app.world)
+            .paths
+            .as_mut()
+            .unwrap()
+            .remove(&0)
+            .unwrap();
+        let mut wave_state = app.world.query::<&WaveState>();
+        let mut enemies = app.world.query::<&Enemy>();
+
+        // the enemy due stays queued while its spawn has no path
+        run_until_tick(&mut app, 30, frame_length);
+        assert_eq!(wave_state.single(&app.world).spawned, 0);
+        assert_eq!(enemies.iter(&app.world).count(), 0);
+
+        // and comes once there is one
+        let mut paths = td_paths.single_mut(&mut app.world);
+        paths.paths.as_mut().unwrap().insert(0, path);
+        run_until_tick(&mut app, 31, frame_length);
+        assert_eq!(wave_state.single(&app.world).spawned, 1);
+        assert_eq!(enemies.iter(&app.world).count(), 1);
+    }
+
+    #[test]
+    fn enemies_reroute_around_new_mountains() {
+        // the new paths apply on the tick the mountains go up
+        let config = GameConfig {
+            seed: Some(42),
+            path_calculation_ticks: 0,
+            ..default()
+        };
+        let (mut app, tx) = setup_tower_defense_headless(config);
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 60, frame_length);
+        let goal = app.world.query::<&TDPaths>().single(&app.world).goal;
+        let mut enemies = app.world.query::<(Entity, &Enemy, &Moves)>();
+        let before: Vec<(Entity, u32)> = enemies
+            .iter(&app.world)
+            .map(|(entity, enemy, _)| (entity, enemy.health))
+            .collect();
+        assert!(!before.is_empty());
+
+        // block the tiles the enemies are heading to
+        let targets: Vec<Hex> = enemies
+            .iter(&app.world)
+            .map(|(_, _, moves)| moves.to)
+            .filter(|hex| *hex != goal)
+            .collect();
+        for hex in targets.iter() {
+            tx.send(parse_command(&format!("toggle {},{}", hex.x, hex.y)).unwrap())
+                .unwrap();
+        }
+        run_until_tick(&mut app, 61, frame_length);
+
+        let mut tiles = app.world.query::<(&Coords, &Tile)>();
+        let mountains: Vec<Hex> = tiles
+            .iter(&app.world)
+            .filter(|(coords, tile)| {
+                tile.tile_type == TileType::Mountain && targets.contains(&coords.0)
+            })
+            .map(|(coords, _)| coords.0)
+            .collect();
+        assert!(!mountains.is_empty());
+        // nobody was removed or hurt, and only enemies walking off
+        // a mountain that went up under them are still on one
+        for (entity, health) in before {
+            let (_, enemy, moves) = enemies.get(&app.world, entity).unwrap();
+            assert_eq!(enemy.health, health);
+            assert!(!mountains.contains(&moves.to) || mountains.contains(&moves.from));
+        }
+    }
+
+    #[test]
+    fn enemies_wait_for_paths_around_new_mountains() {
+        let (mut app, tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 60, frame_length);
+        let td_paths = app.world.query::<&TDPaths>().single(&app.world).clone();
+        let mut enemies = app.world.query::<(Entity, &Moves)>();
+        let (entity, moves) = enemies
+            .iter(&app.world)
+            .find(|(_, moves)| !td_paths.is_endpoint(moves.to) && moves.lerp < 0.5)
+            .unwrap();
+        let (blocked, lerp) = (moves.to, moves.lerp);
+
+        // the enemy stops at the border of the new mountain while the old paths are in use
+        tx.send(parse_command(&format!("toggle {},{}", blocked.x, blocked.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 61, frame_length);
+        let events = app.world.resource::<Events<CommandResult>>();
+        let results: Vec<_> = events
+            .get_reader()
+            .iter(events)
+            .map(|result| result.result.clone())
+            .collect();
+        assert_eq!(results, vec![Ok(())]);
+        let due = 60 + app.world.resource::<Config>().0.path_calculation_ticks as u64;
+        for tick in 62..=due {
+            run_until_tick(&mut app, tick, frame_length);
+            let (_, moves) = enemies.get(&app.world, entity).unwrap();
+            assert_eq!(moves.to, blocked);
+            assert!(moves.lerp > lerp && moves.lerp <= 0.5);
+        }
+
+        // and walks back once the new paths lead around it
+        run_until_tick(&mut app, due + 30, frame_length);
+        let (_, moves) = enemies.get(&app.world, entity).unwrap();
+        assert!(moves.from != blocked && moves.to != blocked);
+    }
+
+    #[test]
+    fn enemies_off_the_path_follow_the_flow_field() {
+        let (mut app, _tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 60, frame_length);
+
+        // move an enemy onto a tile far from the path
+        let paths = app.world.query::<&TDPaths>().single(&app.world).clone();
+        let mut tiles = app
+            .world
+            .query_filtered::<&Coords, (Without<OnPath>, Without<IsGoal>, Without<IsSpawn>)>();
+        let mut off_path: Vec<Hex> = tiles
+            .iter(&app.world)
+            .map(|coords| coords.0)
+            .filter(|hex| paths.flow_field.cost(*hex).is_some())
+            .collect();
+        off_path.sort_by_key(|hex| (std::cmp::Reverse(paths.flow_field.cost(*hex)), hex.x, hex.y));
+        let start = off_path[0];
+        let mut enemies = app.world.query::<(Entity, &mut Moves)>();
+        let (entity, mut moves) = enemies.iter_mut(&mut app.world).next().unwrap();
+        (moves.from, moves.to, moves.lerp) = (start, start, 0.);
+
+        // it stays on the board and walks downhill towards the goal
+        run_until_tick(&mut app, 360, frame_length);
+        let (_, moves) = enemies.get(&app.world, entity).unwrap();
+        assert!(paths.flow_field.cost(moves.from) < paths.flow_field.cost(start));
+    }
+
+    #[test]
+    fn paths_are_calculated_in_the_background() {
+        let config = GameConfig {
+            map: Some("config/map.yaml".to_string()),
+            path_calculation_ticks: 10,
+            ..default()
+        };
+        let (mut app, tx) = setup_tower_defense_headless(config);
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        let frame_length = tick_length(&app);
+        let mut paths = app.world.query::<&TDPaths>();
+        let spawn_path = |app: &App, paths: &mut QueryState<&TDPaths>| {
+            paths.single(&app.world).paths.as_ref().unwrap()[&0].clone()
+        };
+
+        // the first paths are there right away
+        run_until_tick(&mut app, 1, frame_length);
+        let old_path = spawn_path(&app, &mut paths);
+        let blocked = old_path[old_path.len() / 2];
+        tx.send(parse_command(&format!("toggle {},{}", blocked.x, blocked.y)).unwrap())
+            .unwrap();
+
+        // the old paths stay in use until the new ones are due
+        run_until_tick(&mut app, 11, frame_length);
+        assert_eq!(spawn_path(&app, &mut paths), old_path);
+        run_until_tick(&mut app, 12, frame_length);
+        let new_path = spawn_path(&app, &mut paths);
+        assert!(!new_path.contains(&blocked));
+        assert_eq!(new_path.last(), old_path.last());
+        let mut pending = app.world.query::<&PendingPaths>();
+        assert!(pending.get_single(&app.world).is_err());
+    }
+
+    #[test]
+    fn ticks_wait_for_paths_still_being_calculated() {
+        let (mut app, _tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        let (board, td_paths) = {
+            let mut board = app.world.query::<(Entity, &TDPaths)>();
+            let (board, td_paths) = board.single(&app.world);
+            (board, td_paths.clone())
+        };
+
+        // paths due on tick 3 whose calculation only ends when it is released
+        let (release, released) = crossbeam_channel::bounded::<()>(1);
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            released.recv().unwrap();
+            CalculatedPaths {
+                flow_field: td_paths.flow_field,
+                paths: td_paths.paths.unwrap(),
+            }
+        });
+        app.world.entity_mut(board).insert(PendingPaths {
+            calculation: PathCalculation::Running(task),
+            due: 3,
+        });
+        run_until_tick(&mut app, 2, frame_length);
+        for _ in 0..10 {
+            run_frame(&mut app, frame_length);
         }
+        assert_eq!(app.world.resource::<SimulationTick>().0, 2);
+
+        // they apply on the tick they are due once they are there
+        release.send(()).unwrap();
+        run_until_tick(&mut app, 3, frame_length);
+        let mut pending = app.world.query::<&PendingPaths>();
+        assert!(pending.get_single(&app.world).is_err());
     }
 }
@@ -12,14 +12,12 @@ pub fn handle_input(
     mut current: Local<Hex>,
     windows: Query<&Window, With<PrimaryWindow>>,
     camera_q: Query<(&Camera, &GlobalTransform), With<TDCamera>>,
-    mut tiles: Query<(Option<&OnPath>, Option<&IsGoal>, Option<&HasTower>)>,
+    tiles: Query<(Option<&IsSpawn>, Option<&IsGoal>, Option<&HasTower>)>,
     mut cursor: Query<&mut Tile>,
     grid: Query<&mut HexGrid>,
     buttons: Res<Input<MouseButton>>,
     selected_tower: Res<SelectedTower>,
-    mut tower_create: EventWriter<CreateTower>,
-    mut recalculate_paths: EventWriter<RecalculateEnemyPaths>,
-    mut toggle_tiles: EventWriter<ToggleTile>,
+    command_sender: Res<GameCommandSender>,
 ) {
     let window = windows.single();
     let (camera, camera_transform) = camera_q.single();
@@ -35,35 +33,41 @@ pub fn handle_input(
         // change tiles according to buttons
         if buttons.just_pressed(MouseButton::Left) {
             event!(Level::INFO, "Left click pressed at {:?}", hex_pos);
-            if let Some(entity) = grid.entities.get(&hex_pos) {
-                // functionality for left button
-                let (on_path, _is_goal, _) = tiles.get_mut(*entity).unwrap();
-                if on_path.is_some() {
-                    event!(Level::INFO, "Clicked tile on path");
-                    // send recaulculate command
-                    recalculate_paths.send(RecalculateEnemyPaths);
-                }
-                // send toggle tile command
-                toggle_tiles.send(ToggleTile { hex_pos })
+            if grid.entities.contains_key(&hex_pos) {
+                // send toggle tile command, paths are recalculated
+                // by `toggle_tile` if the tile is on path
+                send_command(&command_sender, TDCommand::Toggle(ToggleTile { hex_pos }));
             }
         }
         // functionality for right button press
         if buttons.just_pressed(MouseButton::Right) {
             if let Some(entity) = grid.entities.get(&hex_pos) {
-                if let Ok((on_path, is_goal, has_tower)) = tiles.get(*entity) {
+                if let Ok((is_spawn, is_goal, has_tower)) = tiles.get(*entity) {
                     event!(Level::INFO, "Right clicked to place tower");
-                    if is_goal.is_some() || on_path.is_some() || has_tower.is_some() {
+                    if is_goal.is_some() || is_spawn.is_some() || has_tower.is_some() {
                         return;
                     } else {
                         // spawn tower command
-                        tower_create.send(CreateTower {
-                            tower_type: selected_tower.selected,
-                            hex_pos,
-                        });
+                        send_command(
+                            &command_sender,
+                            TDCommand::Tower(CreateTower {
+                                tower_type: selected_tower.selected.clone(),
+                                hex_pos,
+                            }),
+                        );
                     }
                 }
             }
         }
+        // middle button sells the tower on the tile
+        if buttons.just_pressed(MouseButton::Middle) {
+            if let Some(entity) = grid.entities.get(&hex_pos) {
+                if let Ok((_, _, Some(_))) = tiles.get(*entity) {
+                    event!(Level::INFO, "Middle clicked to sell tower");
+                    send_command(&command_sender, TDCommand::Sell(SellTower { hex_pos }));
+                }
+            }
+        }
 
         if hex_pos == *current {
             return;
@@ -85,6 +89,13 @@ pub fn handle_input(
     }
 }
 
+// queues a command to be executed on the next simulation tick
+pub fn send_command(command_sender: &GameCommandSender, command: TDCommand) {
+    if command_sender.0.try_send(command).is_err() {
+        event!(Level::WARN, "Unable to queue command");
+    }
+}
+
 pub fn camera_zoom(
     mut camera_projection: Query<&mut OrthographicProjection, With<TDCamera>>,
     time: Res<Time>,
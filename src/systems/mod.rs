@@ -1,25 +1,37 @@
 use crate::components::*;
+use crate::config::GameConfig;
 use crate::resources::*;
 use crate::utils::*;
 use crate::AppState;
 use bevy::prelude::*;
 use hexx::HexLayout;
-use rand::{rngs::StdRng, SeedableRng};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
 use std::collections::HashMap;
 use tracing::{event, Level};
 
 mod communication;
+mod effects;
 mod enemies;
 mod input;
+mod projectiles;
+mod query;
 mod render;
+mod replay;
+mod snapshot;
 mod tiles;
 mod towers;
 mod ui;
 
 pub use communication::*;
+pub use effects::*;
 pub use enemies::*;
 pub use input::*;
+pub use projectiles::*;
+pub use query::*;
 pub use render::*;
+pub use replay::*;
+pub use snapshot::*;
 pub use tiles::*;
 pub use towers::*;
 pub use ui::*;
@@ -36,27 +48,51 @@ pub fn destroy_board(mut commands: Commands, board: Query<Entity, With<TDBoard>>
 // ticks the game_over timer upon entering the GameOver state
 pub fn game_over_timer(
     mut game_timer: Query<&mut TDTimers>,
-    time: Res<Time>,
+    fixed_time: Res<FixedTime>,
     mut next_state: ResMut<NextState<AppState>>,
 ) {
     let mut td_timers = game_timer.single_mut();
-    td_timers.game_over_timer.tick(time.delta());
+    td_timers.game_over_timer.tick(fixed_time.period);
     if td_timers.game_over_timer.just_finished() {
         event!(Level::INFO, "Game over timer over!");
         next_state.set(AppState::Restart);
     }
 }
 
-// ticks the game timer
+// counts the simulation ticks run in game
+pub fn advance_simulation_tick(mut tick: ResMut<SimulationTick>) {
+    tick.0 += 1;
+}
+
+// ticks the game timer and ends the game in defeat once the goal runs out of
+// lives, or in victory once every wave is beaten or the timer runs out
 pub fn game_timer(
-    mut game_timer: Query<&mut GameTimer>,
-    time: Res<Time>,
+    mut board: Query<(&mut GameTimer, &WaveState, &mut GameResult)>,
+    goal: Query<&Lives, With<IsGoal>>,
+    enemies: Query<(), With<Enemy>>,
+    fixed_time: Res<FixedTime>,
+    config: Res<Config>,
     mut next_state: ResMut<NextState<AppState>>,
 ) {
-    let mut game_timer = game_timer.single_mut();
-    game_timer.0.tick(time.delta());
-    if game_timer.0.just_finished() {
-        event!(Level::INFO, "Game over!");
+    let (mut game_timer, wave_state, mut game_result) = board.single_mut();
+    game_timer.0.tick(fixed_time.period);
+    let outcome_config = &config.0.outcome;
+    let waves = &config.0.waves;
+    let waves_beaten = outcome_config.win_after_waves
+        && !waves.is_empty()
+        && wave_state.finished(waves)
+        && enemies.is_empty();
+    let timer_survived = outcome_config.win_on_timer && game_timer.0.just_finished();
+    let outcome = if goal.iter().any(|lives| lives.0 == 0) {
+        Some(Outcome::Defeat)
+    } else if waves_beaten || timer_survived {
+        Some(Outcome::Victory)
+    } else {
+        None
+    };
+    if let Some(outcome) = outcome {
+        event!(Level::INFO, "Game over! {:?}", outcome);
+        game_result.0 = Some(outcome);
         next_state.set(AppState::GameOver);
     }
 }
@@ -80,7 +116,7 @@ pub fn setup_resources(
     } else {
         rand::random()
     };
-    let rng = StdRng::seed_from_u64(seed);
+    let rng = ChaCha12Rng::seed_from_u64(seed);
     let layout = HexLayout {
         hex_size: config.0.hex_size,
         ..default()
@@ -89,6 +125,7 @@ pub fn setup_resources(
     let hex_mesh = meshes.add(hexagonal_plane(&layout));
     let enemy_mesh: Handle<Mesh> = meshes.add(shape::Circle::new(5.).into());
     let tower_mesh: Handle<Mesh> = meshes.add(shape::Quad::new(Vec2::new(8., 8.)).into());
+    let projectile_mesh: Handle<Mesh> = meshes.add(shape::Circle::new(2.).into());
 
     // materials
     let plains_mat = materials.add(Color::GREEN.into());
@@ -115,13 +152,12 @@ pub fn setup_resources(
 
     let enemy_mat = materials.add(Color::BLACK.into());
 
-    let tower_mat_small = materials.add(Color::ORANGE.into());
-    let tower_mat_medium = materials.add(Color::ORANGE_RED.into());
-    let tower_mat_large = materials.add(Color::CRIMSON.into());
-
     let tile_meshes = HashMap::from([(MeshType::Hex, hex_mesh)]);
     let enemy_meshes = HashMap::from([(MeshType::Enemy, enemy_mesh)]);
-    let tower_meshes = HashMap::from([(MeshType::Tower, tower_mesh)]);
+    let tower_meshes = HashMap::from([
+        (MeshType::Tower, tower_mesh),
+        (MeshType::Projectile, projectile_mesh),
+    ]);
 
     let tile_materials = HashMap::from([
         (MaterialType::Plains, plains_mat),
@@ -132,11 +168,9 @@ pub fn setup_resources(
         (MaterialType::Target, target_mat),
     ]);
     let enemy_materials = HashMap::from([(MaterialType::Enemy, enemy_mat)]);
-    let tower_materials = HashMap::from([
-        (TowerType::Small, tower_mat_small),
-        (TowerType::Medium, tower_mat_medium),
-        (TowerType::Large, tower_mat_large),
-    ]);
+    let enemy_type_materials = enemy_type_materials(&config.0, &mut materials);
+    let effect_materials = effect_materials(&config.0, &mut materials);
+    let tower_materials = tower_materials(&config.0, &mut materials);
     let damaging_materials = HashMap::from([
         (DamageLevel::Low, damaging_mat_low),
         (DamageLevel::Medium, damaging_mat_medium),
@@ -153,44 +187,107 @@ pub fn setup_resources(
     commands.insert_resource(EnemyVisuals {
         meshes: enemy_meshes,
         materials: enemy_materials,
+        type_materials: enemy_type_materials,
+        effect_materials,
     });
     event!(Level::INFO, "Tower Visuals");
     commands.insert_resource(TowerVisuals {
         meshes: tower_meshes,
         materials: tower_materials,
+        default_material: materials.add(Color::ORANGE.into()),
+        projectile_material: materials.add(Color::ALICE_BLUE.into()),
     });
     event!(Level::INFO, "Rng");
     commands.insert_resource(TDRng(rng));
+    commands.insert_resource(TDSeed(seed));
+}
+
+// materials of the enemy types of `config`
+pub fn enemy_type_materials(
+    config: &GameConfig,
+    materials: &mut Assets<ColorMaterial>,
+) -> HashMap<String, Handle<ColorMaterial>> {
+    config
+        .enemy_config
+        .enemy_types
+        .iter()
+        .map(|(name, enemy_type)| {
+            let color = Color::hex(&enemy_type.color).unwrap_or_else(|_| {
+                event!(Level::WARN, "Invalid color for enemy type {name}");
+                Color::BLACK
+            });
+            (name.clone(), materials.add(color.into()))
+        })
+        .collect()
+}
+
+// materials of the status effects of `config`
+pub fn effect_materials(
+    config: &GameConfig,
+    materials: &mut Assets<ColorMaterial>,
+) -> HashMap<String, Handle<ColorMaterial>> {
+    config
+        .status_effects
+        .iter()
+        .map(|(name, effect)| {
+            let color = Color::hex(&effect.color).unwrap_or_else(|_| {
+                event!(Level::WARN, "Invalid color for status effect {name}");
+                Color::WHITE
+            });
+            (name.clone(), materials.add(color.into()))
+        })
+        .collect()
+}
+
+// materials of the tower kinds of `config`
+pub fn tower_materials(
+    config: &GameConfig,
+    materials: &mut Assets<ColorMaterial>,
+) -> HashMap<String, Handle<ColorMaterial>> {
+    config
+        .tower_config
+        .tower_types
+        .iter()
+        .map(|(name, tower_type)| {
+            let color = Color::hex(&tower_type.color).unwrap_or_else(|_| {
+                event!(Level::WARN, "Invalid color for tower type {name}");
+                Color::ORANGE
+            });
+            (name.clone(), materials.add(color.into()))
+        })
+        .collect()
 }
 
 // spawns the "game over" text upon game over timer resolution
 pub fn show_game_over_text(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    board: Query<(Entity, &ScoreBoard), With<TDBoard>>,
+    board: Query<(Entity, &GameResult), With<TDBoard>>,
 ) {
-    let (board, score_board) = board.single();
+    let (board, game_result) = board.single();
     let font = asset_server.load("fonts/FiraSans-Bold.ttf");
     let text_style = TextStyle {
         font: font.clone(),
         font_size: 100.0,
         color: Color::BLACK,
     };
-    let res = if score_board.enemy_score < score_board.player_score {
-        "WIN"
-    } else {
-        "LOSE"
+    let text = match game_result.0 {
+        Some(Outcome::Victory) => "Game over!\nYOU WIN",
+        Some(Outcome::Defeat) => "Game over!\nYOU LOSE",
+        None => "Game over!",
     };
-    let text = format!("Game over!\nYOU {}", res);
     let text_alignment = TextAlignment::Center;
     commands
-        .spawn((Text2dBundle {
-            text: Text::from_section(text, text_style).with_alignment(text_alignment),
-            transform: Transform::default().with_translation(Vec3 {
-                z: 10.,
+        .spawn((
+            Text2dBundle {
+                text: Text::from_section(text, text_style).with_alignment(text_alignment),
+                transform: Transform::default().with_translation(Vec3 {
+                    z: 10.,
+                    ..default()
+                }),
                 ..default()
-            }),
-            ..default()
-        },))
+            },
+            GameOverText,
+        ))
         .set_parent(board);
 }
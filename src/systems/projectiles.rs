@@ -0,0 +1,147 @@
+use crate::components::*;
+use crate::flow_field::FlowField;
+use crate::resources::*;
+use crate::systems::{apply_status_effect, hit_damage};
+use crate::utils::*;
+use bevy::prelude::*;
+use bevy::sprite::MaterialMesh2dBundle;
+use hexx::Hex;
+
+// projectile towers fire at the enemy in range picked by their targeting mode
+pub fn fire_projectiles(
+    mut commands: Commands,
+    mut towers: Query<(&Tower, &Coords, &TargetingMode, &mut Reload)>,
+    enemies: Query<(Entity, &Enemy, &Moves, &Transform)>,
+    board: Query<(Entity, &HexGrid, &TDPaths), With<TDBoard>>,
+    tower_visuals: Res<TowerVisuals>,
+    fixed_time: Res<FixedTime>,
+    config: Res<Config>,
+) {
+    let (board, grid, td_paths) = board.single();
+    // world distance between two neighbouring tiles
+    let tile_length = grid
+        .layout
+        .hex_to_world_pos(Hex::X)
+        .distance(grid.layout.hex_to_world_pos(Hex::ZERO));
+    for (tower, coords, targeting, mut reload) in towers.iter_mut() {
+        let Some(tower_config) = config.0.tower_config.tower_types.get(&tower.tower_type.0) else {
+            continue;
+        };
+        let Some(projectile) = tower_config.projectile else {
+            continue;
+        };
+        if !reload.0.tick(fixed_time.period).just_finished() {
+            continue;
+        }
+        let stats = tower_config.stats(tower.level);
+        let position = grid.layout.hex_to_world_pos(coords.0);
+        let target = enemies
+            .iter()
+            .filter(|(_, _, _, transform)| {
+                let enemy_hex = grid
+                    .layout
+                    .world_pos_to_hex(transform.translation.truncate());
+                coords.0.unsigned_distance_to(enemy_hex) <= stats.range
+            })
+            .map(|(entity, enemy, moves, transform)| {
+                let distance = position.distance(transform.translation.truncate());
+                let priority = match targeting {
+                    TargetingMode::First => -distance_to_goal(&td_paths.flow_field, moves),
+                    TargetingMode::Last => distance_to_goal(&td_paths.flow_field, moves),
+                    TargetingMode::Strongest => enemy.health as f32,
+                    TargetingMode::Weakest => -(enemy.health as f32),
+                    TargetingMode::Closest => -distance,
+                };
+                (entity, priority)
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        let Some((target, _)) = target else {
+            // stay loaded until an enemy comes in range
+            let duration = reload.0.duration();
+            reload.0.set_elapsed(duration);
+            continue;
+        };
+        commands
+            .spawn((
+                projectile_bundle(
+                    &tower_visuals,
+                    Transform::from_translation(position.extend(4.)),
+                ),
+                Projectile {
+                    target,
+                    damage: stats.damage,
+                    damage_type: tower_config.damage_type.clone(),
+                    effect: tower_config.effect.clone(),
+                    speed: projectile.speed * tile_length,
+                },
+            ))
+            .set_parent(board);
+    }
+}
+
+// moves projectiles towards their targets, dying enemies are
+// removed by `handle_enemy_damage`
+pub fn move_projectiles(
+    mut commands: Commands,
+    mut projectiles: Query<(Entity, &Projectile, &mut Transform), Without<Enemy>>,
+    mut enemies: Query<(&mut Enemy, &EnemyType, &mut StatusEffects, &Transform)>,
+    fixed_time: Res<FixedTime>,
+    config: Res<Config>,
+) {
+    let step = fixed_time.period.as_secs_f32();
+    for (entity, projectile, mut transform) in projectiles.iter_mut() {
+        let Ok((mut enemy, enemy_type, mut effects, target)) = enemies.get_mut(projectile.target)
+        else {
+            // the target died or reached the goal
+            commands.entity(entity).despawn();
+            continue;
+        };
+        let to_target = target.translation.truncate() - transform.translation.truncate();
+        let distance = projectile.speed * step;
+        if to_target.length() <= distance {
+            let damage = hit_damage(
+                &config.0,
+                enemy_type,
+                projectile.damage,
+                &projectile.damage_type,
+            );
+            enemy.health = enemy.health.saturating_sub(damage);
+            if let Some(effect) = &projectile.effect {
+                if apply_status_effect(
+                    effects.bypass_change_detection(),
+                    effect,
+                    &config.0.status_effects,
+                ) {
+                    effects.set_changed();
+                }
+            }
+            commands.entity(entity).despawn();
+        } else {
+            transform.translation += (to_target.normalize() * distance).extend(0.);
+        }
+    }
+}
+
+// how far an enemy still has to go along the flow field
+fn distance_to_goal(flow_field: &FlowField, moves: &Moves) -> f32 {
+    let from = flow_field.cost(moves.from).unwrap_or(u32::MAX) as f32;
+    let to = flow_field.cost(moves.to).unwrap_or(u32::MAX) as f32;
+    from + (to - from) * moves.lerp
+}
+
+pub fn projectile_bundle(
+    tower_visuals: &TowerVisuals,
+    transform: Transform,
+) -> MaterialMesh2dBundle<ColorMaterial> {
+    MaterialMesh2dBundle {
+        mesh: tower_visuals
+            .meshes
+            .get(&MeshType::Projectile)
+            .unwrap()
+            .clone()
+            .into(),
+        material: tower_visuals.projectile_material.clone(),
+        transform,
+        ..default()
+    }
+}
@@ -0,0 +1,233 @@
+use crate::communication::*;
+use crate::components::*;
+use crate::query::*;
+use crate::resources::*;
+use bevy::prelude::*;
+use tracing::event;
+use tracing::Level;
+
+// answers queries with the board as it is at the end of the tick
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn answer_queries(
+    mut queries: EventReader<Requested<TDQuery>>,
+    board: Query<(
+        &Budget,
+        &ScoreBoard,
+        &GameTimer,
+        &WaveState,
+        &GameResult,
+        &TDPaths,
+        &HexGrid,
+    )>,
+    tiles: Query<(
+        &Tile,
+        Option<&IsGoal>,
+        Option<&IsSpawn>,
+        Option<&OnPath>,
+        Option<&Damaging>,
+        Option<&Children>,
+    )>,
+    towers: Query<(&Tower, &TargetingMode, &Coords)>,
+    enemies: Query<(Entity, &Enemy, &EnemyType, &Moves, &Transform)>,
+    goal: Query<&Lives, With<IsGoal>>,
+    tick: Res<SimulationTick>,
+    config: Res<Config>,
+) {
+    if queries.is_empty() {
+        return;
+    }
+    let (budget, score_board, game_timer, wave_state, game_result, td_paths, grid) = board.single();
+    let tower_view = |(tower, targeting, coords): (&Tower, &TargetingMode, &Coords)| TowerView {
+        hex: coords.0,
+        tower_type: tower.tower_type.0.clone(),
+        level: tower.level,
+        cost: tower.cost,
+        targeting: *targeting,
+    };
+    for request in queries.iter() {
+        let result = match request.command {
+            TDQuery::State => Ok(QueryResult::State(StateView {
+                tick: tick.0,
+                budget: budget.0,
+                player_score: score_board.player_score,
+                enemy_score: score_board.enemy_score,
+                lives: goal.iter().map(|lives| lives.0).sum(),
+                elapsed: game_timer.0.elapsed_secs(),
+                game_length: game_timer.0.duration().as_secs_f32(),
+                progress: game_timer.0.percent(),
+                wave: wave_state.wave,
+                waves: config.0.waves.len(),
+                result: game_result.0,
+            })),
+            TDQuery::Tile(hex) => grid
+                .entities
+                .get(&hex)
+                .and_then(|entity| tiles.get(*entity).ok())
+                .map(|(tile, is_goal, is_spawn, on_path, damaging, children)| {
+                    QueryResult::Tile(TileView {
+                        hex,
+                        tile_type: tile.tile_type,
+                        is_goal: is_goal.is_some(),
+                        is_spawn: is_spawn.is_some(),
+                        on_path: on_path.is_some(),
+                        damaging: damaging.map(|damaging| damaging.value),
+                        tower: children
+                            .and_then(|children| children.iter().find_map(|c| towers.get(*c).ok()))
+                            .map(tower_view),
+                    })
+                })
+                .ok_or(CommandError::OutOfBounds(hex)),
+            TDQuery::Enemies => {
+                let mut enemies: Vec<_> = enemies.iter().collect();
+                // in the order they were spawned
+                enemies.sort_by_key(|(entity, ..)| *entity);
+                Ok(QueryResult::Enemies(
+                    enemies
+                        .into_iter()
+                        .map(|(_, enemy, enemy_type, moves, transform)| EnemyView {
+                            enemy_type: enemy_type.0.clone(),
+                            health: enemy.health,
+                            max_health: enemy.max_health,
+                            from: moves.from,
+                            to: moves.to,
+                            lerp: moves.lerp,
+                            position: transform.translation.truncate(),
+                        })
+                        .collect(),
+                ))
+            }
+            TDQuery::Towers => {
+                let mut towers: Vec<TowerView> = towers.iter().map(tower_view).collect();
+                towers.sort_by_key(|tower| (tower.hex.x, tower.hex.y));
+                Ok(QueryResult::Towers(towers))
+            }
+            TDQuery::Paths => Ok(QueryResult::Paths(td_paths.into())),
+        };
+        let Some(Reply { id, tx }) = &request.reply else {
+            event!(Level::DEBUG, "Nowhere to answer {:?} to", request.command);
+            continue;
+        };
+        let (data, error) = match result {
+            Ok(data) => (Some(data), None),
+            Err(error) => (None, Some(error)),
+        };
+        if tx
+            .send(CommandResponse {
+                id: *id,
+                error,
+                data,
+            })
+            .is_err()
+        {
+            event!(Level::DEBUG, "Client of query {id:?} is gone");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+    use crate::request_sender;
+    use crate::test_utils::*;
+    use crossbeam_channel::unbounded;
+    use hexx::Hex;
+
+    #[test]
+    fn queries_describe_the_board() {
+        let (mut app, tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        let (goal, spawns) = {
+            let td_paths = app.world.query::<&TDPaths>().single(&app.world);
+            (td_paths.goal, td_paths.spawns.clone())
+        };
+        let mut tiles = app.world.query::<(&Coords, &Tile)>();
+        let mut open: Vec<Hex> = tiles
+            .iter(&app.world)
+            .filter(|(coords, tile)| {
+                tile.tile_type == TileType::Plains && coords.0.unsigned_distance_to(goal) == 3
+            })
+            .map(|(coords, _)| coords.0)
+            .collect();
+        open.sort_by_key(|hex| (hex.x, hex.y));
+        let tower = open[0];
+        tx.send(parse_command(&format!("tower {},{} Small", tower.x, tower.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 60, frame_length);
+
+        let requests = request_sender(&app);
+        let (reply_tx, reply_rx) = unbounded();
+        for (id, query) in [
+            "state",
+            &format!("tile {},{}", tower.x, tower.y),
+            &format!("tile {},{}", goal.x, goal.y),
+            "tile 100,100",
+            "enemies",
+            "towers",
+            "paths",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            requests
+                .send(Requested {
+                    command: parse_command(query).unwrap(),
+                    reply: Some(Reply {
+                        id: Some(id as u64),
+                        tx: reply_tx.clone(),
+                    }),
+                })
+                .unwrap();
+        }
+        run_until_tick(&mut app, 61, frame_length);
+        let mut responses: Vec<CommandResponse> = reply_rx.try_iter().collect();
+        responses.sort_by_key(|response| response.id);
+        assert_eq!(responses.len(), 7);
+
+        let budget = app.world.query::<&Budget>().single(&app.world).0;
+        let Some(QueryResult::State(state)) = &responses[0].data else {
+            panic!("no state: {:?}", responses[0]);
+        };
+        assert_eq!((state.tick, state.budget), (61, budget));
+        assert_eq!(state.lives, GameConfig::default().outcome.lives);
+        assert!(state.progress > 0.0 && state.result.is_none());
+
+        let Some(QueryResult::Tile(tile)) = &responses[1].data else {
+            panic!("no tile: {:?}", responses[1]);
+        };
+        assert_eq!(tile.hex, tower);
+        assert_eq!(
+            tile.tower.as_ref().map(|t| t.tower_type.as_str()),
+            Some("Small")
+        );
+        let Some(QueryResult::Tile(tile)) = &responses[2].data else {
+            panic!("no tile: {:?}", responses[2]);
+        };
+        assert!(tile.is_goal && !tile.is_spawn && tile.tower.is_none());
+        assert_eq!(
+            responses[3].error,
+            Some(CommandError::OutOfBounds(Hex::new(100, 100)))
+        );
+
+        let enemy_count = app.world.query::<&Enemy>().iter(&app.world).count();
+        let Some(QueryResult::Enemies(enemies)) = &responses[4].data else {
+            panic!("no enemies: {:?}", responses[4]);
+        };
+        assert!(enemy_count > 0);
+        assert_eq!(enemies.len(), enemy_count);
+        assert!(enemies.iter().all(|enemy| enemy.health <= enemy.max_health));
+        let Some(QueryResult::Towers(towers)) = &responses[5].data else {
+            panic!("no towers: {:?}", responses[5]);
+        };
+        assert_eq!(
+            towers.iter().map(|t| t.hex).collect::<Vec<_>>(),
+            vec![tower]
+        );
+        let Some(QueryResult::Paths(paths)) = &responses[6].data else {
+            panic!("no paths: {:?}", responses[6]);
+        };
+        assert_eq!(paths.spawns, spawns);
+        assert_eq!(paths.paths.as_ref().map(|p| p.len()), Some(spawns.len()));
+    }
+}
@@ -4,6 +4,7 @@ use tracing::{event, Level};
 
 // Renders all tiles
 // TODO: move default bundle setup to setup_tiles_and_board
+#[allow(clippy::type_complexity)]
 pub fn render_tiles(
     mut commands: Commands,
     tile_visuals: Res<TileVisuals>,
@@ -75,6 +76,7 @@ pub fn render_tiles(
 
 // renders damage aoe
 // TODO: Move this into `render_tiles` and simplify
+#[allow(clippy::type_complexity)]
 pub fn render_tower_aoe(
     mut commands: Commands,
     tiles: Query<
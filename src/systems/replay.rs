@@ -0,0 +1,194 @@
+use crate::communication::{Requested, TDCommand};
+use crate::replay::{RecordedCommand, RecordedState, Replay};
+use crate::resources::*;
+use crate::AppState;
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use tracing::event;
+use tracing::Level;
+
+// adds the commands executed this tick to the recording
+pub fn record_commands(
+    mut recorder: ResMut<CommandRecorder>,
+    mut executed_commands: EventReader<Requested<TDCommand>>,
+    tick: Res<SimulationTick>,
+    seed: Res<TDSeed>,
+    config: Res<Config>,
+    state: Res<State<AppState>>,
+) {
+    let replay = recorder
+        .replay
+        .get_or_insert_with(|| Replay::new(seed.0, config.0.clone()));
+    // queries do not change the game, and commands
+    // are refused while the game is paused or over
+    for request in executed_commands.iter().filter(|request| {
+        !matches!(request.command, TDCommand::Query(_))
+            && (state.0 == AppState::InGame || request.command.outside_game())
+    }) {
+        replay.commands.push(RecordedCommand {
+            tick: tick.0,
+            state: recorded_state(&state.0),
+            command: request.command.clone(),
+        });
+    }
+}
+
+// writes the recording to the replay file once the app exits
+pub fn write_replay(recorder: Res<CommandRecorder>, mut exit: EventReader<AppExit>) {
+    if exit.iter().last().is_none() {
+        return;
+    }
+    let Some(replay) = &recorder.replay else {
+        return;
+    };
+    match replay.export(&recorder.path) {
+        Err(e) => event!(Level::WARN, "{e}"),
+        Ok(()) => event!(Level::INFO, "Replay written to {}", recorder.path),
+    }
+}
+
+// sends the replay commands recorded on the current tick in the state they
+// were executed in, commands from anywhere else are only read again once the
+// replay is over
+pub fn replay_commands(
+    mut commands: Commands,
+    mut player: ResMut<ReplayPlayer>,
+    tick: Res<SimulationTick>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut command_send: EventWriter<Requested<TDCommand>>,
+) {
+    let current = recorded_state(&state.0);
+    let is_due = |c: &RecordedCommand| c.tick <= tick.0;
+    while player
+        .commands
+        .front()
+        .is_some_and(|c| is_due(c) && c.state == current)
+    {
+        let recorded = player.commands.pop_front().unwrap();
+        event!(
+            Level::DEBUG,
+            "Replaying command from tick {}: {:?}",
+            recorded.tick,
+            recorded.command
+        );
+        command_send.send(Requested::new(recorded.command));
+    }
+    // commands executed while paused are sent in a pause after the tick, so that a
+    // load is not followed by a tick of the game that was running when it was sent
+    let pause_next = player
+        .commands
+        .front()
+        .is_some_and(|c| is_due(c) && c.state == RecordedState::Paused);
+    match current {
+        RecordedState::Running if pause_next => next_state.set(AppState::Pause),
+        RecordedState::Paused if !pause_next => next_state.set(AppState::InGame),
+        _ => (),
+    }
+    if player.commands.is_empty() {
+        commands.remove_resource::<ReplayPlayer>();
+    }
+}
+
+// the state commands are recorded in, only the ones with a board
+fn recorded_state(state: &AppState) -> RecordedState {
+    match state {
+        AppState::Pause => RecordedState::Paused,
+        AppState::GameOver => RecordedState::Over,
+        _ => RecordedState::Running,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::parse_command;
+    use crate::communication::CommandResult;
+    use crate::play_replay;
+    use crate::record_replay;
+    use crate::setup_tower_defense_headless;
+    use crate::test_utils::*;
+    use bevy::time::TimeUpdateStrategy;
+    use std::time::Instant;
+
+    #[test]
+    fn replay_reproduces_game() {
+        let path = std::env::temp_dir().join("td_replay_reproduces_game.yaml");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        // record a game
+        let (mut app, tx) = headless_app(11);
+        record_replay(&mut app, path);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        send_path_toggles(&mut app, &tx, frame_length);
+        run_until_tick(&mut app, 300, frame_length);
+        let recorded_state = board_state(&mut app);
+        // the replay is written when the app exits
+        assert!(Replay::load(path).is_err());
+        app.world.send_event(AppExit);
+        app.update();
+
+        // play it back
+        let replay = Replay::load(path).unwrap();
+        assert_eq!(replay.seed, 11);
+        assert_eq!(replay.commands.len(), 3);
+        let (mut app, _tx) = setup_tower_defense_headless(replay.config());
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        play_replay(&mut app, replay);
+        run_until_tick(&mut app, 300, frame_length);
+        assert_eq!(recorded_state, board_state(&mut app));
+    }
+
+    #[test]
+    fn replay_reproduces_save_and_load_while_paused() {
+        let path = std::env::temp_dir().join("td_replay_save_and_load_while_paused.yaml");
+        let path = path.to_str().unwrap();
+        let save_path = std::env::temp_dir().join("td_replay_save_and_load_while_paused_save.yaml");
+        let save_path = save_path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        // record a game that goes back to an earlier save while paused
+        let (mut app, tx) = headless_app(11);
+        record_replay(&mut app, path);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        send_path_toggles(&mut app, &tx, frame_length);
+        tx.send(parse_command(&format!("save {save_path}")).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 200, frame_length);
+        let set_state = |app: &mut App, state: AppState| {
+            app.world.resource_mut::<NextState<AppState>>().set(state);
+            run_frame(app, frame_length);
+        };
+        set_state(&mut app, AppState::Pause);
+        tx.send(parse_command(&format!("save {save_path}.paused")).unwrap())
+            .unwrap();
+        tx.send(parse_command(&format!("load {save_path}")).unwrap())
+            .unwrap();
+        run_frame(&mut app, frame_length);
+        let events = app.world.resource::<Events<CommandResult>>();
+        let results: Vec<_> = events
+            .get_reader()
+            .iter(events)
+            .map(|result| result.result.clone())
+            .collect();
+        assert_eq!(results, vec![Ok(()), Ok(())]);
+        set_state(&mut app, AppState::InGame);
+        run_until_tick(&mut app, 300, frame_length);
+        let recorded_state = board_state(&mut app);
+        app.world.send_event(AppExit);
+        app.update();
+
+        // play it back
+        let replay = Replay::load(path).unwrap();
+        assert_eq!(replay.commands.len(), 6);
+        let (mut app, _tx) = setup_tower_defense_headless(replay.config());
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        play_replay(&mut app, replay);
+        run_until_tick(&mut app, 300, frame_length);
+        assert!(!app.world.contains_resource::<ReplayPlayer>());
+        assert_eq!(recorded_state, board_state(&mut app));
+    }
+}
@@ -0,0 +1,532 @@
+use crate::communication::*;
+use crate::components::*;
+use crate::flow_field::step_cost;
+use crate::resources::*;
+use crate::snapshot::*;
+use crate::systems::{
+    calculate_paths, effect_materials, enemy_bundle, enemy_type_materials, projectile_bundle,
+    tower_bundle, tower_materials,
+};
+use crate::AppState;
+use bevy::prelude::*;
+use hexx::{Hex, HexLayout};
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::event;
+use tracing::Level;
+
+// writes the board and everything on it to a snapshot file
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn save_game(
+    mut save_game: EventReader<Requested<SaveGame>>,
+    mut results: EventWriter<CommandResult>,
+    board: Query<(
+        &Budget,
+        &ScoreBoard,
+        &GameTimer,
+        &TDTimers,
+        &WaveState,
+        &GameResult,
+        &TDPaths,
+        &HexGrid,
+        Option<&PendingPaths>,
+    )>,
+    tiles: Query<(
+        &Tile,
+        Option<&IsGoal>,
+        Option<&IsSpawn>,
+        Option<&OnPath>,
+        Option<&Children>,
+    )>,
+    towers: Query<(&Tower, &Reload, &TargetingMode)>,
+    enemies_q: Query<(
+        Entity,
+        &Enemy,
+        &EnemyType,
+        &StatusEffects,
+        &Moves,
+        &Transform,
+    )>,
+    projectiles: Query<(&Projectile, &Transform)>,
+    goal: Query<&Lives, With<IsGoal>>,
+    rng: Res<TDRng>,
+    seed: Res<TDSeed>,
+    tick: Res<SimulationTick>,
+    config: Res<Config>,
+) {
+    for request in save_game.iter() {
+        let save = &request.command;
+        let (
+            budget,
+            score_board,
+            game_timer,
+            td_timers,
+            wave_state,
+            game_result,
+            td_paths,
+            grid,
+            pending,
+        ) = board.single();
+
+        // sorted so that the same board is always saved the same way
+        let mut hexes: Vec<Hex> = grid.entities.keys().copied().collect();
+        hexes.sort_by_key(|hex| (hex.x, hex.y));
+        let tiles = hexes
+            .into_iter()
+            .filter_map(|hex| {
+                let (tile, is_goal, is_spawn, on_path, children) =
+                    tiles.get(*grid.entities.get(&hex)?).ok()?;
+                let tower =
+                    children.and_then(|c| c.iter().find_map(|child| towers.get(*child).ok()));
+                Some(TileSnapshot {
+                    hex,
+                    tile_type: tile.tile_type,
+                    is_goal: is_goal.is_some(),
+                    is_spawn: is_spawn.is_some(),
+                    on_path: on_path.is_some(),
+                    tower: tower.map(|(tower, _, _)| tower.clone()),
+                    tower_reload: tower.map(|(_, reload, _)| (&reload.0).into()),
+                    tower_targeting: tower.map(|(_, _, targeting)| *targeting),
+                })
+            })
+            .collect();
+        let enemies: Vec<EnemySnapshot> = enemies_q
+            .iter()
+            .map(
+                |(_, enemy, enemy_type, effects, moves, transform)| EnemySnapshot {
+                    enemy: enemy.clone(),
+                    enemy_type: enemy_type.clone(),
+                    status_effects: effects.clone(),
+                    moves: moves.clone(),
+                    translation: transform.translation,
+                    scale: transform.scale,
+                },
+            )
+            .collect();
+        // projectiles refer to their target by its position in `enemies`
+        let enemy_entities: Vec<Entity> = enemies_q.iter().map(|(entity, ..)| entity).collect();
+        let projectiles = projectiles
+            .iter()
+            .filter_map(|(projectile, transform)| {
+                Some(ProjectileSnapshot {
+                    target: enemy_entities
+                        .iter()
+                        .position(|entity| *entity == projectile.target)?,
+                    damage: projectile.damage,
+                    damage_type: projectile.damage_type.clone(),
+                    effect: projectile.effect.clone(),
+                    speed: projectile.speed,
+                    translation: transform.translation,
+                })
+            })
+            .collect();
+
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            tick: tick.0,
+            seed: seed.0,
+            rng: rng.0.clone(),
+            config: config.0.clone(),
+            board: BoardSnapshot {
+                budget: budget.0,
+                player_score: score_board.player_score,
+                enemy_score: score_board.enemy_score,
+                lives: goal.single().0,
+                game_timer: (&game_timer.0).into(),
+                enemy_spawn_rate: (&td_timers.enemy_spawn_rate).into(),
+                tower_damaging_rate: (&td_timers.tower_damaging_rate).into(),
+                game_over_timer: (&td_timers.game_over_timer).into(),
+                result: game_result.0,
+                wave_state: wave_state.into(),
+                paths: td_paths.clone(),
+                paths_due: pending.map(|pending| pending.due),
+                tiles,
+                enemies,
+                projectiles,
+            },
+        };
+        match snapshot.export(&save.path) {
+            Err(e) => results.send(request.reject(CommandError::Snapshot(e.to_string()))),
+            Ok(()) => {
+                event!(Level::INFO, "Game saved to {}", save.path);
+                results.send(request.accept());
+            }
+        }
+    }
+}
+
+// replaces the board with the one from a snapshot file, the game goes
+// on from it unless it was saved after the game was over
+#[allow(clippy::too_many_arguments)]
+pub fn load_game(
+    mut commands: Commands,
+    mut load_game: EventReader<Requested<LoadGame>>,
+    mut results: EventWriter<CommandResult>,
+    board: Query<Entity, With<TDBoard>>,
+    mut tower_visuals: ResMut<TowerVisuals>,
+    mut enemy_visuals: ResMut<EnemyVisuals>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut fixed_time: ResMut<FixedTime>,
+    mut rng: ResMut<TDRng>,
+    mut seed: ResMut<TDSeed>,
+    mut tick: ResMut<SimulationTick>,
+    mut config: ResMut<Config>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    // only the last load of a tick is carried out, the ones before are rejected
+    let mut requests: Vec<&Requested<LoadGame>> = load_game.iter().collect();
+    let Some(request) = requests.pop() else {
+        return;
+    };
+    for replaced in requests {
+        results.send(replaced.reject(CommandError::Superseded));
+    }
+    let load = &request.command;
+    let snapshot = match Snapshot::load(&load.path) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            results.send(request.reject(CommandError::Snapshot(e.to_string())));
+            return;
+        }
+    };
+    event!(Level::INFO, "Loading game from {}", load.path);
+
+    for entity in board.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    rng.0 = snapshot.rng;
+    seed.0 = snapshot.seed;
+    tick.0 = snapshot.tick;
+    // saved configs skip `GameConfig::load` as well
+    config.0 = snapshot.config.validated();
+    // the saved config may have other kinds and another tick length than the running one
+    fixed_time.period = Duration::from_secs_f32(config.0.tick_length);
+    tower_visuals.materials = tower_materials(&config.0, &mut materials);
+    enemy_visuals.type_materials = enemy_type_materials(&config.0, &mut materials);
+    enemy_visuals.effect_materials = effect_materials(&config.0, &mut materials);
+    let saved_board = snapshot.board;
+
+    // spawn board
+    let board = commands
+        .spawn((
+            SpatialBundle {
+                visibility: Visibility::Visible,
+                ..Default::default()
+            },
+            TDBoard,
+            TDTimers {
+                enemy_spawn_rate: (&saved_board.enemy_spawn_rate).into(),
+                tower_damaging_rate: (&saved_board.tower_damaging_rate).into(),
+                game_over_timer: (&saved_board.game_over_timer).into(),
+            },
+            Budget(saved_board.budget),
+            ScoreBoard {
+                player_score: saved_board.player_score,
+                enemy_score: saved_board.enemy_score,
+            },
+            GameResult(saved_board.result),
+            GameTimer((&saved_board.game_timer).into()),
+            WaveState::from(&saved_board.wave_state),
+        ))
+        .id();
+
+    // restart a calculation that was running, the tiles have not changed since
+    if let Some(due) = saved_board.paths_due {
+        let step_costs: HashMap<Hex, u32> = saved_board
+            .tiles
+            .iter()
+            .filter_map(|tile| Some((tile.hex, step_cost(tile.tile_type, tile.tower.is_some())?)))
+            .collect();
+        commands.entity(board).insert(calculate_paths(
+            saved_board.paths.goal,
+            saved_board.paths.spawns.clone(),
+            step_costs,
+            due,
+            tick.0,
+        ));
+    }
+
+    // spawn tiles and towers, tower damage is recalculated by `handle_new_towers`
+    let entities: HashMap<Hex, Entity> = saved_board
+        .tiles
+        .into_iter()
+        .map(|saved_tile| {
+            let mut child = commands.spawn((
+                Tile {
+                    tile_type: saved_tile.tile_type,
+                    is_cursor: false,
+                },
+                Coords(saved_tile.hex),
+            ));
+            child.set_parent(board);
+            if saved_tile.is_goal {
+                child.insert((IsGoal, Lives(saved_board.lives)));
+            }
+            if saved_tile.is_spawn {
+                child.insert(IsSpawn);
+            }
+            if saved_tile.on_path {
+                child.insert(OnPath);
+            }
+            let tile_entity = child.id();
+            if let (Some(tower), Some(reload)) = (saved_tile.tower, saved_tile.tower_reload) {
+                let scale = config
+                    .0
+                    .tower_config
+                    .tower_types
+                    .get(&tower.tower_type.0)
+                    .map_or(1., |t| t.stats(tower.level).scale);
+                commands
+                    .spawn((
+                        tower_bundle(&tower_visuals, &tower.tower_type, scale),
+                        tower,
+                        Reload((&reload).into()),
+                        saved_tile.tower_targeting.unwrap_or_default(),
+                        Coords(saved_tile.hex),
+                    ))
+                    .set_parent(tile_entity);
+                commands.entity(tile_entity).insert(HasTower);
+            }
+            (saved_tile.hex, tile_entity)
+        })
+        .collect();
+
+    // spawn enemies and the projectiles flying at them
+    let mut enemies = Vec::new();
+    for saved_enemy in saved_board.enemies {
+        let enemy = commands
+            .spawn((
+                enemy_bundle(
+                    &enemy_visuals,
+                    &saved_enemy.enemy_type,
+                    Transform::from_translation(saved_enemy.translation)
+                        .with_scale(saved_enemy.scale),
+                ),
+                saved_enemy.enemy,
+                saved_enemy.enemy_type,
+                saved_enemy.status_effects,
+                saved_enemy.moves,
+            ))
+            .set_parent(board)
+            .id();
+        enemies.push(enemy);
+    }
+    for saved_projectile in saved_board.projectiles {
+        let Some(target) = enemies.get(saved_projectile.target) else {
+            continue;
+        };
+        commands
+            .spawn((
+                projectile_bundle(
+                    &tower_visuals,
+                    Transform::from_translation(saved_projectile.translation),
+                ),
+                Projectile {
+                    target: *target,
+                    damage: saved_projectile.damage,
+                    damage_type: saved_projectile.damage_type,
+                    effect: saved_projectile.effect,
+                    speed: saved_projectile.speed,
+                },
+            ))
+            .set_parent(board);
+    }
+
+    let layout = HexLayout {
+        hex_size: config.0.hex_size,
+        ..default()
+    };
+    commands
+        .entity(board)
+        .insert((saved_board.paths, HexGrid { entities, layout }));
+    // a paused game stays paused
+    if saved_board.result.is_some() {
+        next_state.set(AppState::GameOver);
+    } else if state.0 == AppState::GameOver {
+        next_state.set(AppState::InGame);
+    }
+    results.send(request.accept());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+    use crate::setup_tower_defense_headless;
+    use crate::test_utils::*;
+    use bevy::time::TimeUpdateStrategy;
+    use std::time::Instant;
+
+    #[test]
+    fn games_load_after_the_game_ends() {
+        let path = std::env::temp_dir().join("td_games_load_after_the_game_ends.yaml");
+        let path = path.to_str().unwrap();
+        let over_path = std::env::temp_dir().join("td_games_load_after_the_game_ends_over.yaml");
+        let over_path = over_path.to_str().unwrap();
+        let config = GameConfig {
+            seed: Some(42),
+            game_length: 1.,
+            ..default()
+        };
+        let (mut app, tx) = setup_tower_defense_headless(config);
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 30, frame_length);
+        tx.send(parse_command(&format!("save {path}")).unwrap())
+            .unwrap();
+        let state = |app: &App| app.world.resource::<State<AppState>>().0.clone();
+        while state(&app) == AppState::InGame {
+            run_frame(&mut app, frame_length);
+        }
+
+        // saving and loading go on while the game is over
+        tx.send(parse_command(&format!("save {over_path}")).unwrap())
+            .unwrap();
+        tx.send(parse_command(&format!("load {path}")).unwrap())
+            .unwrap();
+        run_frame(&mut app, frame_length);
+        run_frame(&mut app, frame_length);
+        // saved on tick 31, the second frame already ran the tick after it
+        assert_eq!(state(&app), AppState::InGame);
+        assert_eq!(app.world.resource::<SimulationTick>().0, 32);
+        let mut result = app.world.query::<&GameResult>();
+        assert_eq!(result.single(&app.world).0, None);
+
+        // a game saved after it was over stays over
+        tx.send(parse_command(&format!("load {over_path}")).unwrap())
+            .unwrap();
+        run_frame(&mut app, frame_length);
+        assert_eq!(state(&app), AppState::GameOver);
+        assert_eq!(result.single(&app.world).0, Some(Outcome::Victory));
+    }
+
+    #[test]
+    fn load_restores_saved_game() {
+        let path = std::env::temp_dir().join("td_load_restores_saved_game.yaml");
+        let path = path.to_str().unwrap();
+
+        // save a game in progress and keep playing it
+        let (mut app, tx) = headless_app(5);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        send_path_toggles(&mut app, &tx, frame_length);
+        let hex_pos = free_tile_next_to_path(&mut app);
+        tx.send(parse_command(&format!("tower {},{} Small", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 200, frame_length);
+        tx.send(parse_command(&format!("save {path}")).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 400, frame_length);
+        let mut played_state = board_state(&mut app);
+
+        // load it into a different game and play to the same tick
+        let (mut app, tx) = headless_app(6);
+        run_until_tick(&mut app, 1, frame_length);
+        // only the last load of a tick is carried out
+        tx.send(parse_command(&format!("load {path}.missing")).unwrap())
+            .unwrap();
+        tx.send(parse_command(&format!("load {path}")).unwrap())
+            .unwrap();
+        run_frame(&mut app, frame_length);
+        let events = app.world.resource::<Events<CommandResult>>();
+        let results: Vec<_> = events
+            .get_reader()
+            .iter(events)
+            .map(|result| result.result.clone())
+            .collect();
+        assert_eq!(results, vec![Err(CommandError::Superseded), Ok(())]);
+        // the loaded game continues from the tick it was saved on
+        run_until_tick(&mut app, 201, frame_length);
+        assert_eq!(app.world.resource::<Config>().0.seed, Some(5));
+        run_until_tick(&mut app, 400, frame_length);
+        let mut loaded_state = board_state(&mut app);
+
+        played_state.3.sort();
+        loaded_state.3.sort();
+        assert!(!played_state.3.is_empty());
+        assert_eq!(played_state, loaded_state);
+    }
+
+    #[test]
+    fn load_uses_the_saved_config() {
+        let path = std::env::temp_dir().join("td_load_uses_the_saved_config.yaml");
+        let path = path.to_str().unwrap();
+
+        // save a game with a tower kind the default config does not have
+        let mut config = GameConfig {
+            seed: Some(5),
+            tick_length: 1. / 30.,
+            ..default()
+        };
+        let mut tower_config = config.tower_config.tower_types["Small"].clone();
+        tower_config.short_name = None;
+        config
+            .tower_config
+            .tower_types
+            .insert("Custom".to_string(), tower_config);
+        let (mut app, tx) = setup_tower_defense_headless(config);
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        let hex_pos = free_tile_next_to_path(&mut app);
+        tx.send(parse_command(&format!("tower {},{} Custom", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 2, frame_length);
+        tx.send(parse_command(&format!("save {path}")).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 3, frame_length);
+
+        // load it into a game with the default config
+        let (mut app, tx) = headless_app(6);
+        let default_length = tick_length(&app);
+        run_until_tick(&mut app, 1, default_length);
+        tx.send(parse_command(&format!("load {path}")).unwrap())
+            .unwrap();
+        run_frame(&mut app, default_length);
+        let events = app.world.resource::<Events<CommandResult>>();
+        let results: Vec<_> = events
+            .get_reader()
+            .iter(events)
+            .map(|result| result.result.clone())
+            .collect();
+        assert_eq!(results, vec![Ok(())]);
+        assert_eq!(tick_length(&app), frame_length);
+        run_frame(&mut app, frame_length);
+        let mut towers = app.world.query::<(&Tower, &Handle<ColorMaterial>)>();
+        let (tower, material) = towers.single(&app.world);
+        assert_eq!(tower.tower_type.0, "Custom");
+        assert_eq!(
+            app.world.resource::<TowerVisuals>().materials.get("Custom"),
+            Some(material)
+        );
+    }
+
+    #[test]
+    fn loaded_configs_are_validated() {
+        let path = std::env::temp_dir().join("td_loaded_configs_are_validated.yaml");
+        let path = path.to_str().unwrap();
+
+        // save a game whose config was edited to something the game can not be played with
+        let (mut app, tx) = headless_app(5);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        let mut config = app.world.resource_mut::<Config>();
+        let runner = config.0.enemy_config.enemy_types.get_mut("runner").unwrap();
+        let (min_health, max_health) = runner.min_max_health;
+        runner.min_max_health = (max_health, min_health);
+        tx.send(parse_command(&format!("save {path}")).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 2, frame_length);
+
+        // loading it fixes the config like loading a config file does
+        tx.send(parse_command(&format!("load {path}")).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 3, frame_length);
+        let config = &app.world.resource::<Config>().0;
+        assert_eq!(
+            config.enemy_config.enemy_types["runner"].min_max_health,
+            (min_health, max_health)
+        );
+    }
+}
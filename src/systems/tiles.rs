@@ -1,6 +1,10 @@
+use crate::config::{GameConfig, MapGenerationConfig, MapGenerator};
+use crate::flow_field::{may_disconnect, step_cost, FlowField};
+use crate::map::{HexMap, MapTile};
+use crate::systems::{reload, tower_bundle};
 use crate::{communication::*, components::*, resources::*, AppState};
 use bevy::prelude::*;
-use hexx::{DiagonalDirection, Hex, HexLayout};
+use hexx::{algorithms::a_star, DiagonalDirection, Hex, HexLayout};
 use rand::prelude::*;
 use std::collections::HashMap;
 use tracing::{event, Level};
@@ -9,27 +13,45 @@ use tracing::{event, Level};
 pub fn handle_removed_paths(
     mut commands: Commands,
     mut removed: RemovedComponents<OnPath>,
-    damaging_tiles: Query<&Children, &Damaging>,
-    // mut place_tower_damage: EventWriter<RefreshTowerDamage>,
+    damaging_tiles: Query<Option<&Children>, With<Damaging>>,
+    damaging_bases: Query<(), With<DamagingBase>>,
 ) {
-    // remove the damaging base
     for removed_path in removed.iter() {
-        if let Some(mut entity_commands) = commands.get_entity(removed_path) {
-            if damaging_tiles.get(removed_path).is_ok() {
-                entity_commands.despawn_descendants();
-                entity_commands.remove::<Damaging>();
-            }
-            entity_commands.insert(Refresh);
-            event!(Level::DEBUG, "Refreshed tile with removed path");
+        if commands.get_entity(removed_path).is_none() {
+            continue;
+        }
+        if let Ok(children) = damaging_tiles.get(removed_path) {
+            remove_damaging(&mut commands, removed_path, children, &damaging_bases);
+        }
+        commands.entity(removed_path).insert(Refresh);
+        event!(Level::DEBUG, "Refreshed tile with removed path");
+    }
+}
+
+// takes the damage off a tile together with the base showing it, towers
+// may stand on tiles that are only about to leave the path and are kept
+pub fn remove_damaging(
+    commands: &mut Commands,
+    tile: Entity,
+    children: Option<&Children>,
+    damaging_bases: &Query<(), With<DamagingBase>>,
+) {
+    for child in children.into_iter().flatten() {
+        if damaging_bases.contains(*child) {
+            commands.entity(*child).despawn();
         }
     }
+    commands.entity(tile).remove::<Damaging>();
 }
 
-// Spawns board and tiles from config
+// Spawns board and tiles from the map file in config
+// or from a procedurally generated map
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_board_and_tiles(
     mut commands: Commands,
     mut rng: ResMut<TDRng>,
     config: Res<Config>,
+    tower_visuals: Res<TowerVisuals>,
     mut recalculate_paths: EventWriter<RecalculateEnemyPaths>,
     mut next_state: ResMut<NextState<AppState>>,
 ) {
@@ -61,52 +83,81 @@ pub fn spawn_board_and_tiles(
             td_timers,
             budget,
             ScoreBoard::default(),
+            GameResult::default(),
             game_timer,
+            WaveState::new(&config.0.waves),
         ))
         .id();
 
-    // find spawn locations
-    let spawns: Vec<Hex> = DiagonalDirection::iter()
-        .map(|d| {
-            Hex::ZERO
-                .ring_edge(config.0.map_radius, d)
-                .choose(&mut rng.0)
-                .unwrap()
-        })
-        .collect();
+    // load or generate the map
+    let map = match config.0.map.as_ref().map(|path| HexMap::load(path)) {
+        Some(Ok(map)) => map,
+        Some(Err(e)) => {
+            event!(Level::WARN, "{e}, generating map instead");
+            generate_map(&config.0, &mut rng.0)
+        }
+        None => generate_map(&config.0, &mut rng.0),
+    };
 
     // create tile entities
-    let entities: HashMap<Hex, Entity> = Hex::ZERO
-        .spiral_range(0..=config.0.map_radius)
-        .enumerate()
-        .map(|(_i, coord)| {
-            let random = rng.0.gen_range(0..=3); // TODO: make this percentage based in config
-            let tile_type = match random {
-                0..=2 => TileType::Plains,
-                _ => TileType::Mountain,
-            };
+    let entities: HashMap<Hex, Entity> = map
+        .all_tiles()
+        .into_iter()
+        .map(|map_tile| {
+            let coord = map_tile.hex;
             let mut child = commands.spawn((
                 Tile {
-                    tile_type,
+                    tile_type: map_tile.tile_type,
                     is_cursor: false,
                 },
                 Coords(coord),
             ));
 
             child.set_parent(board);
-            if coord == Hex::ZERO {
-                child.insert(IsGoal);
-            } else if spawns.contains(&coord) {
+            if coord == map.goal {
+                child.insert((IsGoal, Lives(config.0.outcome.lives)));
+            } else if map.spawns.contains(&coord) {
                 child.insert(IsSpawn);
             }
             (coord, child.id())
         })
         .collect();
 
+    // place the map towers
+    for map_tower in map.towers.iter() {
+        let tile_entity = *entities.get(&map_tower.hex).unwrap();
+        let Some(tower_config) = config
+            .0
+            .tower_config
+            .tower_types
+            .get(&map_tower.tower_type.0)
+        else {
+            event!(Level::WARN, "Unknown tower type {}", map_tower.tower_type.0);
+            continue;
+        };
+        let stats = tower_config.stats(0);
+        commands
+            .spawn((
+                tower_bundle(&tower_visuals, &map_tower.tower_type, stats.scale),
+                Tower {
+                    tower_type: map_tower.tower_type.clone(),
+                    cost: tower_config.cost,
+                    level: 0,
+                },
+                reload(&stats),
+                TargetingMode::default(),
+                Coords(map_tower.hex),
+            ))
+            .set_parent(tile_entity);
+        commands.entity(tile_entity).insert(HasTower);
+    }
+
     // setup paths
     let td_pathes = TDPaths {
-        spawns,
+        spawns: map.spawns,
+        goal: map.goal,
         paths: None,
+        flow_field: FlowField::default(),
     };
 
     // setup hex grid
@@ -125,24 +176,426 @@ pub fn spawn_board_and_tiles(
     next_state.set(AppState::InGame);
 }
 
+// Generates a hexagon map of radius `map_radius` with the goal in the center
+// and a spawn on each diagonal edge, using the generator set in `map_generation`
+pub fn generate_map(config: &GameConfig, rng: &mut impl Rng) -> HexMap {
+    // find spawn locations
+    let spawns: Vec<Hex> = DiagonalDirection::iter()
+        .map(|d| {
+            Hex::ZERO
+                .ring_edge(config.map_radius, d)
+                .choose(rng)
+                .unwrap()
+        })
+        .collect();
+    let goal = Hex::ZERO;
+
+    let generation = &config.map_generation;
+    let hexes: Vec<Hex> = Hex::ZERO.spiral_range(0..=config.map_radius).collect();
+    let mut tile_types = match generation.generator {
+        MapGenerator::Random => random_terrain(&hexes, generation, rng),
+        MapGenerator::Noise => noise_terrain(&hexes, generation, rng),
+        MapGenerator::CellularAutomata => cellular_automata_terrain(&hexes, generation, rng),
+    };
+    if generation.ensure_connectivity {
+        connect_spawns(&hexes, &mut tile_types, &spawns, goal);
+    }
+
+    HexMap {
+        radius: None,
+        tiles: hexes
+            .into_iter()
+            .zip(tile_types)
+            .map(|(hex, tile_type)| MapTile { hex, tile_type })
+            .collect(),
+        spawns,
+        goal,
+        towers: Vec::new(),
+    }
+}
+
+// each tile is independently a mountain with a chance of `mountain_ratio`
+fn random_terrain(
+    hexes: &[Hex],
+    generation: &MapGenerationConfig,
+    rng: &mut impl Rng,
+) -> Vec<TileType> {
+    let mountain_ratio = generation.mountain_ratio.clamp(0., 1.) as f64;
+    hexes
+        .iter()
+        .map(|_| {
+            if rng.gen_bool(mountain_ratio) {
+                TileType::Mountain
+            } else {
+                TileType::Plains
+            }
+        })
+        .collect()
+}
+
+// mountains follow the ridges of fractal value noise, leaving open valleys
+// between them, exactly `mountain_ratio` of the tiles become mountains
+fn noise_terrain(
+    hexes: &[Hex],
+    generation: &MapGenerationConfig,
+    rng: &mut impl Rng,
+) -> Vec<TileType> {
+    let seed: u64 = rng.gen();
+    let scale = generation.noise_scale.max(1.);
+    let ridges: Vec<f32> = hexes
+        .iter()
+        .map(|hex| {
+            // axial to cartesian coordinates so features are not skewed
+            let x = (hex.x as f32 + hex.y as f32 * 0.5) / scale;
+            let y = hex.y as f32 * 0.866_025_4 / scale;
+            let noise = fractal_noise(seed, x, y);
+            1. - (2. * noise - 1.).abs()
+        })
+        .collect();
+
+    let mut sorted = ridges.clone();
+    sorted.sort_by(|a, b| b.total_cmp(a));
+    let mountains = (hexes.len() as f32 * generation.mountain_ratio.clamp(0., 1.)) as usize;
+    let threshold = sorted.get(mountains).copied().unwrap_or(f32::NEG_INFINITY);
+    ridges
+        .into_iter()
+        .map(|ridge| {
+            if ridge > threshold {
+                TileType::Mountain
+            } else {
+                TileType::Plains
+            }
+        })
+        .collect()
+}
+
+// random mountains smoothed into caves and ridges, a tile becomes a mountain
+// with at least 4 mountain neighbours and plains with at most 2
+fn cellular_automata_terrain(
+    hexes: &[Hex],
+    generation: &MapGenerationConfig,
+    rng: &mut impl Rng,
+) -> Vec<TileType> {
+    let mut tile_types: HashMap<Hex, TileType> = hexes
+        .iter()
+        .copied()
+        .zip(random_terrain(hexes, generation, rng))
+        .collect();
+    for _ in 0..generation.smoothing_passes {
+        tile_types = hexes
+            .iter()
+            .map(|hex| {
+                let mountain_neighbours = hex
+                    .all_neighbors()
+                    .iter()
+                    .filter(|n| tile_types.get(n) == Some(&TileType::Mountain))
+                    .count();
+                let tile_type = match mountain_neighbours {
+                    0..=2 => TileType::Plains,
+                    3 => tile_types[hex],
+                    _ => TileType::Mountain,
+                };
+                (*hex, tile_type)
+            })
+            .collect();
+    }
+    hexes.iter().map(|hex| tile_types[hex]).collect()
+}
+
+// turns the fewest mountains into plains needed for every spawn to have
+// a mountain free way to the goal
+fn connect_spawns(hexes: &[Hex], tile_types: &mut [TileType], spawns: &[Hex], goal: Hex) {
+    let indices: HashMap<Hex, usize> = hexes.iter().enumerate().map(|(i, h)| (*h, i)).collect();
+    for target in spawns.iter().chain(std::iter::once(&goal)) {
+        if let Some(i) = indices.get(target) {
+            tile_types[*i] = TileType::Plains;
+        }
+    }
+    for spawn in spawns.iter() {
+        let path = a_star(*spawn, goal, |hex| {
+            indices.get(&hex).map(|i| match tile_types[*i] {
+                TileType::Plains => 1,
+                TileType::Mountain => 1000,
+            })
+        });
+        for hex in path.into_iter().flatten() {
+            tile_types[indices[&hex]] = TileType::Plains;
+        }
+    }
+}
+
+// value noise with three octaves, in the range 0..1
+fn fractal_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let mut value = 0.;
+    let mut amplitude = 0.5;
+    let mut frequency = 1.;
+    for octave in 0..3 {
+        value += amplitude * value_noise(seed.wrapping_add(octave), x * frequency, y * frequency);
+        amplitude *= 0.5;
+        frequency *= 2.;
+    }
+    value / 0.875
+}
+
+// smoothly interpolated random values on an integer lattice
+fn value_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let smooth = |t: f32| t * t * (3. - 2. * t);
+    let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let top = lerp(lattice(seed, x0, y0), lattice(seed, x0 + 1, y0), tx);
+    let bottom = lerp(lattice(seed, x0, y0 + 1), lattice(seed, x0 + 1, y0 + 1), tx);
+    lerp(top, bottom, ty)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// random value in 0..1 for a lattice point (splitmix64)
+fn lattice(seed: u64, x: i64, y: i64) -> f32 {
+    let mut z = seed
+        .wrapping_add((x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+        .wrapping_add((y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+// toggles tiles between plains and mountains, refusing to cut a spawn off from the goal
+#[allow(clippy::type_complexity)]
 pub fn toggle_tile(
-    mut toggle_tiles: EventReader<ToggleTile>,
-    mut tiles: Query<(&mut Tile, Option<&OnPath>)>,
+    mut toggle_tiles: EventReader<Requested<ToggleTile>>,
+    mut tiles: Query<(&mut Tile, Option<&HasTower>)>,
     mut recalculate_enemy_paths: EventWriter<RecalculateEnemyPaths>,
-    grid: Query<&HexGrid>,
+    mut results: EventWriter<CommandResult>,
+    board: Query<(&HexGrid, &TDPaths)>,
+    endpoints: Query<(), Or<(With<IsGoal>, With<IsSpawn>)>>,
 ) {
-    let grid = grid.single();
-    for t in toggle_tiles.iter() {
-        if let Ok((mut tile, on_path)) = tiles.get_mut(*grid.entities.get(&t.hex_pos).unwrap()) {
-            event!(Level::DEBUG, "Toggling tile at {:?}", t.hex_pos);
-            match tile.tile_type {
-                TileType::Plains => tile.tile_type = TileType::Mountain,
-                TileType::Mountain => tile.tile_type = TileType::Plains,
+    let (grid, td_paths) = board.single();
+    for request in toggle_tiles.iter() {
+        let t = &request.command;
+        let Some(tile_entity) = grid.entities.get(&t.hex_pos) else {
+            results.send(request.reject(CommandError::OutOfBounds(t.hex_pos)));
+            continue;
+        };
+        let Ok((tile, _)) = tiles.get(*tile_entity) else {
+            results.send(request.reject(CommandError::OutOfBounds(t.hex_pos)));
+            continue;
+        };
+        // the goal and spawns stay plains
+        if endpoints.contains(*tile_entity) {
+            results.send(request.reject(CommandError::TileOccupied(t.hex_pos)));
+            continue;
+        }
+        // mountains can not be walked through, keep every spawn connected
+        let step = |hex: Hex| {
+            if hex == t.hex_pos {
+                return None;
             }
-            if on_path.is_some() {
-                recalculate_enemy_paths.send(RecalculateEnemyPaths);
+            let (tile, has_tower) = tiles.get(*grid.entities.get(&hex)?).ok()?;
+            step_cost(tile.tile_type, has_tower.is_some())
+        };
+        if tile.tile_type == TileType::Plains
+            && (td_paths.is_endpoint(t.hex_pos)
+                || may_disconnect(t.hex_pos, |hex| step(hex).is_some()))
+            && !FlowField::new(td_paths.goal, step).connects(&td_paths.connected_spawns())
+        {
+            results.send(request.reject(CommandError::DisconnectsSpawn(t.hex_pos)));
+            continue;
+        }
+        let (mut tile, _) = tiles.get_mut(*tile_entity).unwrap();
+        event!(Level::DEBUG, "Toggling tile at {:?}", t.hex_pos);
+        match tile.tile_type {
+            TileType::Plains => tile.tile_type = TileType::Mountain,
+            TileType::Mountain => tile.tile_type = TileType::Plains,
+        }
+        // a new mountain may block a path and a removed one may open a shorter one
+        recalculate_enemy_paths.send(RecalculateEnemyPaths);
+        results.send(request.accept());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup_tower_defense_headless;
+    use crate::test_utils::*;
+    use bevy::time::TimeUpdateStrategy;
+    use rand_chacha::ChaCha12Rng;
+    use std::collections::HashSet;
+    use std::time::Instant;
+
+    fn generate(generator: MapGenerator) -> HexMap {
+        let config = GameConfig {
+            map_generation: MapGenerationConfig {
+                generator,
+                mountain_ratio: 0.4,
+                ensure_connectivity: true,
+                ..default()
+            },
+            ..default()
+        };
+        generate_map(&config, &mut ChaCha12Rng::seed_from_u64(3))
+    }
+
+    // plains reachable from the goal without crossing mountains
+    fn reachable_from_goal(map: &HexMap) -> HashSet<Hex> {
+        let plains: HashSet<Hex> = map
+            .tiles
+            .iter()
+            .filter(|t| t.tile_type == TileType::Plains)
+            .map(|t| t.hex)
+            .collect();
+        let mut reached = HashSet::from([map.goal]);
+        let mut frontier = vec![map.goal];
+        while let Some(hex) = frontier.pop() {
+            for neighbor in hex.all_neighbors() {
+                if plains.contains(&neighbor) && reached.insert(neighbor) {
+                    frontier.push(neighbor);
+                }
             }
-            // TODO: calculate everytime tile is toggled
         }
+        reached
+    }
+
+    #[test]
+    fn generated_maps_connect_spawns() {
+        for generator in [
+            MapGenerator::Random,
+            MapGenerator::Noise,
+            MapGenerator::CellularAutomata,
+        ] {
+            let map = generate(generator);
+            assert!(map.validate().is_ok());
+            let reached = reachable_from_goal(&map);
+            assert!(
+                map.spawns.iter().all(|s| reached.contains(s)),
+                "{generator:?} map has unreachable spawns"
+            );
+        }
+    }
+
+    #[test]
+    fn noise_map_has_mountain_ratio() {
+        let mut config = GameConfig::default();
+        config.map_generation.generator = MapGenerator::Noise;
+        // clearing paths would turn mountains back into plains
+        config.map_generation.ensure_connectivity = false;
+        let map = generate_map(&config, &mut ChaCha12Rng::seed_from_u64(3));
+        let mountains = map
+            .tiles
+            .iter()
+            .filter(|t| t.tile_type == TileType::Mountain)
+            .count();
+        let expected = map.tiles.len() as f32 * config.map_generation.mountain_ratio;
+        assert!((mountains as f32 - expected).abs() <= 1.);
+    }
+
+    #[test]
+    fn headless_game_loads_map() {
+        let config = GameConfig {
+            map: Some("config/map.yaml".to_string()),
+            ..default()
+        };
+        let (mut app, _tx) = setup_tower_defense_headless(config);
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+
+        let mut goal = app.world.query_filtered::<&Coords, With<IsGoal>>();
+        assert_eq!(goal.single(&app.world).0, Hex::new(-6, 3));
+        let mut paths = app.world.query::<&TDPaths>();
+        let td_paths = paths.single(&app.world);
+        assert_eq!(td_paths.spawns, vec![Hex::new(11, -5), Hex::new(4, 4)]);
+        assert!(td_paths
+            .paths
+            .as_ref()
+            .unwrap()
+            .values()
+            .all(|path| path.last() == Some(&Hex::new(-6, 3))));
+        let mut towers = app.world.query::<&Tower>();
+        assert_eq!(towers.iter(&app.world).count(), 2);
+    }
+
+    #[test]
+    fn spawns_can_not_be_cut_off() {
+        let (mut app, tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        let (goal, spawns) = {
+            let td_paths = app.world.query::<&TDPaths>().single(&app.world);
+            (td_paths.goal, td_paths.spawns.clone())
+        };
+        let mut tiles = app.world.query::<(&Coords, &Tile)>();
+        let mut open: Vec<Hex> = tiles
+            .iter(&app.world)
+            .filter(|(coords, tile)| {
+                tile.tile_type == TileType::Plains
+                    && coords.0.unsigned_distance_to(goal) == 1
+                    && !spawns.contains(&coords.0)
+            })
+            .map(|(coords, _)| coords.0)
+            .collect();
+        open.sort_by_key(|hex| (hex.x, hex.y));
+
+        // wall the goal in, the last gap can be closed neither by a mountain nor a tower
+        let (last, others) = open.split_last().unwrap();
+        for hex in others.iter().chain([last]) {
+            tx.send(parse_command(&format!("toggle {},{}", hex.x, hex.y)).unwrap())
+                .unwrap();
+        }
+        tx.send(parse_command(&format!("tower {},{} Small", last.x, last.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 2, frame_length);
+
+        let events = app.world.resource::<Events<CommandResult>>();
+        let rejected: Vec<CommandError> = events
+            .get_reader()
+            .iter(events)
+            .filter_map(|result| result.result.clone().err())
+            .collect();
+        assert_eq!(rejected, vec![CommandError::DisconnectsSpawn(*last); 2]);
+        let due = 2 + app.world.resource::<Config>().0.path_calculation_ticks as u64;
+        run_until_tick(&mut app, due, frame_length);
+        let td_paths = app.world.query::<&TDPaths>().single(&app.world);
+        let paths = td_paths.paths.as_ref().unwrap();
+        assert_eq!(paths.len(), spawns.len());
+        assert!(paths.values().all(|path| path[path.len() - 2] == *last));
+    }
+
+    #[test]
+    fn goal_and_spawns_can_not_be_toggled() {
+        let (mut app, tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        let td_paths = app.world.query::<&TDPaths>().single(&app.world).clone();
+        let spawn = td_paths.spawns[0];
+
+        for hex in [td_paths.goal, spawn] {
+            tx.send(parse_command(&format!("toggle {},{}", hex.x, hex.y)).unwrap())
+                .unwrap();
+        }
+        run_until_tick(&mut app, 2, frame_length);
+        let events = app.world.resource::<Events<CommandResult>>();
+        let rejected: Vec<CommandError> = events
+            .get_reader()
+            .iter(events)
+            .filter_map(|result| result.result.clone().err())
+            .collect();
+        assert_eq!(
+            rejected,
+            vec![
+                CommandError::TileOccupied(td_paths.goal),
+                CommandError::TileOccupied(spawn),
+            ]
+        );
+        let mut tiles = app
+            .world
+            .query_filtered::<&Tile, Or<(With<IsGoal>, With<IsSpawn>)>>();
+        assert!(tiles
+            .iter(&app.world)
+            .all(|tile| tile.tile_type == TileType::Plains));
     }
 }
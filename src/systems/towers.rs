@@ -1,35 +1,54 @@
 use crate::communication::*;
 use crate::components::*;
-use crate::config::TowerConfig;
+use crate::config::{TowerConfig, TowerStats};
+use crate::flow_field::{may_disconnect, step_cost, FlowField};
 use crate::resources::*;
+use crate::systems::remove_damaging;
 use crate::utils::*;
 use bevy::prelude::*;
 use bevy::sprite::MaterialMesh2dBundle;
+use hexx::Hex;
+use std::time::Duration;
 use tracing::event;
 use tracing::Level;
 
+#[allow(clippy::type_complexity)]
 pub fn handle_new_towers(
     towers: Query<(Entity, &Tower, &Coords), Or<(Changed<Tower>, Changed<Refresh>)>>,
     mut tiles: Query<Option<&mut Damaging>, With<OnPath>>,
     mut commands: Commands,
     grid: Query<&HexGrid>,
+    config: Res<Config>,
 ) {
     let grid = grid.single();
     for (entity, tower, hex_pos) in towers.iter() {
-        for hex in hex_pos.0.spiral_range(0..=tower.tower_type.range()) {
+        // projectile towers hit their target instead of the tiles in range
+        let Some(tower_config) = config
+            .0
+            .tower_config
+            .tower_types
+            .get(&tower.tower_type.0)
+            .filter(|t| t.projectile.is_none())
+        else {
+            continue;
+        };
+        let TowerStats { range, damage, .. } = tower_config.stats(tower.level);
+        let (damage_type, effect) = (&tower_config.damage_type, tower_config.effect.as_ref());
+        for hex in hex_pos.0.spiral_range(0..=range) {
             if let Some(entity) = grid.entities.get(&hex) {
                 if let Ok(is_damaging) = tiles.get_mut(*entity) {
-                    let damage = tower.tower_type.damage();
                     if let Some(mut damaging) = is_damaging {
                         event!(
                             Level::INFO,
                             "Added damage to damaging tile at hex: {:?}",
                             hex_pos
                         );
-                        damaging.value += damage;
+                        damaging.add(damage, damage_type, effect);
                     } else {
                         event!(Level::INFO, "Inserted damaging at hex {:?}", hex);
-                        commands.entity(*entity).insert(Damaging { value: damage });
+                        let mut damaging = Damaging::default();
+                        damaging.add(damage, damage_type, effect);
+                        commands.entity(*entity).insert(damaging);
                     }
                 }
             }
@@ -57,88 +76,520 @@ pub fn refresh_damaging_tiles(
     redraw_tower_damage.clear();
 }
 
-// removes towers on path
-pub fn remove_towers_on_path(
+// spawns towers from event channel, towers block the way
+// so a tower may not cut a spawn off from the goal
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn spawn_tower(
     mut commands: Commands,
-    tiles_on_path_with_tower: Query<(Entity, &Children), (With<HasTower>, With<OnPath>)>,
-    towers: Query<Entity, With<Tower>>,
+    mut create_tower: EventReader<Requested<CreateTower>>,
+    mut recalculate_enemy_paths: EventWriter<RecalculateEnemyPaths>,
+    mut results: EventWriter<CommandResult>,
+    tower_visuals: Res<TowerVisuals>,
+    mut budget: Query<&mut Budget>,
+    board: Query<(&HexGrid, &TDPaths)>,
+    config: Res<Config>,
+    tiles: Query<(&Tile, Option<&HasTower>, Option<&IsGoal>, Option<&IsSpawn>)>,
 ) {
-    for (tile_entity, children) in tiles_on_path_with_tower.iter() {
-        for child in children.iter() {
-            if let Ok(tower_entity) = towers.get(*child) {
-                commands.entity(tower_entity).despawn();
-                commands.entity(tile_entity).remove::<HasTower>();
-                // TODO: remove damage from existing damage bases here?
+    if create_tower.is_empty() {
+        return;
+    }
+    let (grid, td_paths) = board.single();
+    // towers spawned this tick are only on the tiles once commands are applied
+    let mut placed: Vec<Hex> = Vec::new();
+    for request in create_tower.iter() {
+        let t = &request.command;
+        let Some(tile_entity) = grid.entities.get(&t.hex_pos).copied() else {
+            results.send(request.reject(CommandError::OutOfBounds(t.hex_pos)));
+            continue;
+        };
+        let (_, has_tower, is_goal, is_spawn) = tiles.get(tile_entity).unwrap();
+        if has_tower.is_some()
+            || is_goal.is_some()
+            || is_spawn.is_some()
+            || placed.contains(&t.hex_pos)
+        {
+            results.send(request.reject(CommandError::TileOccupied(t.hex_pos)));
+            continue;
+        }
+        // short names stand for the full name of the kind
+        let Some((name, tower_config)) = config.0.tower_config.find(&t.tower_type.0) else {
+            results.send(request.reject(CommandError::UnknownTowerType {
+                name: t.tower_type.0.clone(),
+                kinds: config.0.tower_config.kinds(),
+            }));
+            continue;
+        };
+        let tower_type = TowerType(name.clone());
+        let cost = tower_config.cost;
+        let mut budget = budget.single_mut();
+        if cost > budget.0 {
+            results.send(request.reject(CommandError::InsufficientBudget {
+                budget: budget.0,
+                cost,
+            }));
+            continue;
+        }
+        let step = |hex: Hex| {
+            if hex == t.hex_pos || placed.contains(&hex) {
+                return None;
             }
+            let (tile, has_tower, ..) = tiles.get(*grid.entities.get(&hex)?).ok()?;
+            step_cost(tile.tile_type, has_tower.is_some())
+        };
+        if (td_paths.is_endpoint(t.hex_pos) || may_disconnect(t.hex_pos, |hex| step(hex).is_some()))
+            && !FlowField::new(td_paths.goal, step).connects(&td_paths.connected_spawns())
+        {
+            results.send(request.reject(CommandError::DisconnectsSpawn(t.hex_pos)));
+            continue;
         }
+
+        let stats = tower_config.stats(0);
+        budget.0 = budget.0.saturating_sub(cost);
+        commands
+            .spawn((
+                tower_bundle(&tower_visuals, &tower_type, stats.scale),
+                Tower {
+                    tower_type,
+                    cost,
+                    level: 0,
+                },
+                reload(&stats),
+                TargetingMode::default(),
+                Coords(t.hex_pos),
+            ))
+            .set_parent(tile_entity);
+        commands.entity(tile_entity).insert(HasTower);
+        placed.push(t.hex_pos);
+        // the flow field has to lead around the new tower
+        recalculate_enemy_paths.send(RecalculateEnemyPaths);
+        results.send(request.accept());
     }
 }
 
-// spawns towers from event channel
-#[allow(clippy::type_complexity)]
-pub fn spawn_tower(
+// upgrades towers to their next level, their damage is
+// added back with the new stats by `handle_new_towers`
+#[allow(clippy::too_many_arguments)]
+pub fn upgrade_tower(
     mut commands: Commands,
-    mut create_tower: EventReader<CreateTower>,
-    tower_visuals: Res<TowerVisuals>,
+    mut upgrade_tower: EventReader<Requested<UpgradeTower>>,
+    mut results: EventWriter<CommandResult>,
+    mut towers: Query<(&mut Tower, &mut Transform, &mut Reload)>,
+    tiles: Query<&Children, With<HasTower>>,
+    mut damaging: Query<(&mut Damaging, Option<&Children>)>,
+    damaging_bases: Query<(), With<DamagingBase>>,
     mut budget: Query<&mut Budget>,
     grid: Query<&HexGrid>,
     config: Res<Config>,
-    unplaceable_tiles: Query<(
-        Option<&HasTower>,
-        Option<&OnPath>,
-        Option<&IsGoal>,
-        Option<&IsSpawn>,
-    )>,
 ) {
-    if create_tower.is_empty() {
+    if upgrade_tower.is_empty() {
         return;
     }
     let grid = grid.single();
-    for t in create_tower.iter() {
-        let tile_entity = *grid.entities.get(&t.hex_pos).unwrap();
-        // if there is already a tower on the tile, skip it
-        let (has_tower, on_path, is_goal, is_spawn) = unplaceable_tiles.get(tile_entity).unwrap();
-        if has_tower.is_some() || on_path.is_some() || is_goal.is_some() || is_spawn.is_some() {
+    for request in upgrade_tower.iter() {
+        let u = &request.command;
+        let Some((_, tower_entity)) = tower_on(u.hex_pos, grid, &tiles, |e| towers.contains(e))
+        else {
+            results.send(request.reject(CommandError::NoTower(u.hex_pos)));
+            continue;
+        };
+        let (mut tower, mut transform, mut reload) = towers.get_mut(tower_entity).unwrap();
+        let Some(tower_config) = config.0.tower_config.tower_types.get(&tower.tower_type.0) else {
+            results.send(request.reject(CommandError::UnknownTowerType {
+                name: tower.tower_type.0.clone(),
+                kinds: config.0.tower_config.kinds(),
+            }));
+            continue;
+        };
+        let Some(upgrade) = tower_config.upgrades.get(tower.level as usize) else {
+            results.send(request.reject(CommandError::FullyUpgraded(u.hex_pos)));
+            continue;
+        };
+        let mut budget = budget.single_mut();
+        if upgrade.cost > budget.0 {
+            results.send(request.reject(CommandError::InsufficientBudget {
+                budget: budget.0,
+                cost: upgrade.cost,
+            }));
             continue;
         }
+        budget.0 -= upgrade.cost;
+        remove_tower_damage(
+            &mut commands,
+            &mut damaging,
+            &damaging_bases,
+            grid,
+            u.hex_pos,
+            tower_config,
+            tower.level,
+        );
+        tower.level += 1;
+        tower.cost += upgrade.cost;
+        let stats = tower_config.stats(tower.level);
+        transform.scale = Vec3::splat(stats.scale);
+        reload
+            .0
+            .set_duration(Duration::from_secs_f32(1. / stats.fire_rate.max(0.01)));
+        event!(
+            Level::INFO,
+            "Upgraded tower at {:?} to level {}",
+            u.hex_pos,
+            tower.level
+        );
+        results.send(request.accept());
+    }
+}
 
-        let tower_type = t.tower_type;
-        let (cost, scale) = {
-            let TowerConfig { cost, scale, .. } =
-                config.0.tower_config.tower_type.get(&t.tower_type).unwrap();
-            (*cost, *scale)
+// removes sold towers, refunding part of what was spent on them
+#[allow(clippy::too_many_arguments)]
+pub fn sell_tower(
+    mut commands: Commands,
+    mut sell_tower: EventReader<Requested<SellTower>>,
+    mut results: EventWriter<CommandResult>,
+    towers: Query<&Tower>,
+    tiles: Query<&Children, With<HasTower>>,
+    mut damaging: Query<(&mut Damaging, Option<&Children>)>,
+    damaging_bases: Query<(), With<DamagingBase>>,
+    mut budget: Query<&mut Budget>,
+    mut recalculate_enemy_paths: EventWriter<RecalculateEnemyPaths>,
+    grid: Query<&HexGrid>,
+    config: Res<Config>,
+) {
+    if sell_tower.is_empty() {
+        return;
+    }
+    let grid = grid.single();
+    for request in sell_tower.iter() {
+        let s = &request.command;
+        let Some((tile_entity, tower_entity)) =
+            tower_on(s.hex_pos, grid, &tiles, |e| towers.contains(e))
+        else {
+            results.send(request.reject(CommandError::NoTower(s.hex_pos)));
+            continue;
         };
-        let mut budget = budget.single_mut();
-        if cost > budget.0 {
-            event!(
-                Level::WARN,
-                "Not enough resources for tower. Budget: {}, Cost: {}",
-                budget.0,
-                cost
+        let tower = towers.get(tower_entity).unwrap();
+        if let Some(tower_config) = config.0.tower_config.tower_types.get(&tower.tower_type.0) {
+            remove_tower_damage(
+                &mut commands,
+                &mut damaging,
+                &damaging_bases,
+                grid,
+                s.hex_pos,
+                tower_config,
+                tower.level,
             );
+        }
+        let refund = (tower.cost as f32 * config.0.tower_config.refund_ratio) as u32;
+        budget.single_mut().0 += refund;
+        commands.entity(tower_entity).despawn();
+        commands.entity(tile_entity).remove::<HasTower>();
+        recalculate_enemy_paths.send(RecalculateEnemyPaths);
+        event!(Level::INFO, "Sold tower at {:?} for {}", s.hex_pos, refund);
+        results.send(request.accept());
+    }
+}
+
+// changes which enemies towers shoot at
+pub fn set_targeting(
+    mut set_targeting: EventReader<Requested<SetTargeting>>,
+    mut results: EventWriter<CommandResult>,
+    mut towers: Query<&mut TargetingMode>,
+    tiles: Query<&Children, With<HasTower>>,
+    grid: Query<&HexGrid>,
+) {
+    if set_targeting.is_empty() {
+        return;
+    }
+    let grid = grid.single();
+    for request in set_targeting.iter() {
+        let t = &request.command;
+        let Some((_, tower_entity)) = tower_on(t.hex_pos, grid, &tiles, |e| towers.contains(e))
+        else {
+            results.send(request.reject(CommandError::NoTower(t.hex_pos)));
+            continue;
+        };
+        *towers.get_mut(tower_entity).unwrap() = t.mode;
+        results.send(request.accept());
+    }
+}
+
+// the tile at `hex_pos` and the tower on it, `is_tower` tells
+// the tower apart from the other children of the tile
+pub fn tower_on(
+    hex_pos: Hex,
+    grid: &HexGrid,
+    tiles: &Query<&Children, With<HasTower>>,
+    is_tower: impl Fn(Entity) -> bool,
+) -> Option<(Entity, Entity)> {
+    let tile = *grid.entities.get(&hex_pos)?;
+    let tower = tiles
+        .get(tile)
+        .ok()?
+        .iter()
+        .copied()
+        .find(|c| is_tower(*c))?;
+    Some((tile, tower))
+}
+
+// takes the damage of a tower off the tiles in its range
+pub fn remove_tower_damage(
+    commands: &mut Commands,
+    damaging: &mut Query<(&mut Damaging, Option<&Children>)>,
+    damaging_bases: &Query<(), With<DamagingBase>>,
+    grid: &HexGrid,
+    hex_pos: Hex,
+    tower_config: &TowerConfig,
+    level: u32,
+) {
+    if tower_config.projectile.is_some() {
+        return;
+    }
+    let stats = tower_config.stats(level);
+    for hex in hex_pos.spiral_range(0..=stats.range) {
+        let Some(entity) = grid.entities.get(&hex) else {
             continue;
-        } else {
-            budget.0 = budget.0.saturating_sub(cost);
-            commands
-                .spawn((
-                    MaterialMesh2dBundle {
-                        mesh: tower_visuals
-                            .meshes
-                            .get(&MeshType::Tower)
-                            .unwrap()
-                            .clone()
-                            .into(),
-                        material: tower_visuals.materials.get(&tower_type).unwrap().clone(),
-                        transform: Transform::default()
-                            .with_translation(Vec3 { z: 2., ..default() })
-                            .with_scale(Vec3::splat(scale)),
-                        ..default()
-                    },
-                    Tower { tower_type, cost },
-                    Coords(t.hex_pos),
-                ))
-                .set_parent(tile_entity);
-            commands.entity(tile_entity).insert(HasTower);
+        };
+        if let Ok((mut damaging, children)) = damaging.get_mut(*entity) {
+            damaging.remove(
+                stats.damage,
+                &tower_config.damage_type,
+                tower_config.effect.as_ref(),
+            );
+            if damaging.is_empty() {
+                remove_damaging(commands, *entity, children, damaging_bases);
+            }
         }
     }
 }
+
+// visuals of a tower, placed relative to its tile
+pub fn tower_bundle(
+    tower_visuals: &TowerVisuals,
+    tower_type: &TowerType,
+    scale: f32,
+) -> MaterialMesh2dBundle<ColorMaterial> {
+    MaterialMesh2dBundle {
+        mesh: tower_visuals
+            .meshes
+            .get(&MeshType::Tower)
+            .unwrap()
+            .clone()
+            .into(),
+        material: tower_visuals
+            .materials
+            .get(&tower_type.0)
+            .unwrap_or(&tower_visuals.default_material)
+            .clone(),
+        transform: Transform::default()
+            .with_translation(Vec3 { z: 2., ..default() })
+            .with_scale(Vec3::splat(scale)),
+        ..default()
+    }
+}
+
+// a tower that is ready to fire after its first reload
+pub fn reload(stats: &TowerStats) -> Reload {
+    Reload(Timer::from_seconds(
+        1. / stats.fire_rate.max(0.01),
+        TimerMode::Repeating,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::config::GameConfig;
+    use crate::setup_tower_defense_headless;
+    use crate::test_utils::*;
+    use bevy::time::TimeUpdateStrategy;
+    use std::time::Instant;
+
+    #[test]
+    fn headless_game_accepts_commands() {
+        let (mut app, tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        let starting_budget = app.world.resource::<Config>().0.starting_budget;
+
+        let hex_pos = free_tile_next_to_path(&mut app);
+        tx.send(parse_command(&format!("tower {},{} Small", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 2, frame_length);
+
+        let mut towers = app.world.query::<&Tower>();
+        assert_eq!(towers.iter(&app.world).count(), 1);
+        let mut budget = app.world.query::<&Budget>();
+        assert!(budget.single(&app.world).0 < starting_budget);
+    }
+
+    #[test]
+    fn towers_come_from_config() {
+        let mut config = GameConfig {
+            seed: Some(42),
+            ..default()
+        };
+        let sniper = config::TowerConfig {
+            short_name: None,
+            range: 6,
+            damage: 10,
+            damage_type: "physical".to_string(),
+            fire_rate: 0.5,
+            cost: 30,
+            upgrades: Vec::new(),
+            scale: 1.,
+            color: "#0000ff".to_string(),
+            projectile: None,
+            effect: None,
+        };
+        config
+            .tower_config
+            .tower_types
+            .insert("Sniper".to_string(), sniper);
+        let (mut app, tx) = setup_tower_defense_headless(config);
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+
+        let hex_pos = free_tile_next_to_path(&mut app);
+        tx.send(parse_command(&format!("tower {},{} Sniper", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 2, frame_length);
+
+        let mut towers = app.world.query::<&Tower>();
+        let tower = towers.single(&app.world);
+        assert_eq!(tower.tower_type.0, "Sniper");
+        assert_eq!(tower.cost, 30);
+        let mut damaging = app.world.query::<&Damaging>();
+        assert!(damaging.iter(&app.world).all(|d| d.value == 10));
+    }
+
+    #[test]
+    fn area_towers_put_effects_on_their_tiles() {
+        let (mut app, tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+
+        let hex_pos = free_tile_next_to_path(&mut app);
+        tx.send(parse_command(&format!("tower {},{} Frost", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 2, frame_length);
+        let mut damaging = app.world.query::<&Damaging>();
+        assert!(damaging.iter(&app.world).next().is_some());
+        assert!(damaging
+            .iter(&app.world)
+            .all(|d| d.value == 0 && d.damage.is_empty() && d.effects["slow"] == 1));
+
+        let mut slowed = false;
+        for tick in 3..=600 {
+            run_until_tick(&mut app, tick, frame_length);
+            let mut effects = app.world.query::<&StatusEffects>();
+            slowed |= effects
+                .iter(&app.world)
+                .any(|effects| effects.0.iter().any(|effect| effect.name == "slow"));
+        }
+        assert!(slowed);
+
+        // selling the tower takes its effect off the tiles again
+        tx.send(parse_command(&format!("sell {},{}", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 601, frame_length);
+        assert_eq!(damaging.iter(&app.world).count(), 0);
+    }
+
+    #[test]
+    fn projectile_towers_shoot_enemies() {
+        let (mut app, tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+
+        let hex_pos = free_tile_next_to_path(&mut app);
+        tx.send(parse_command(&format!("tower {},{} Archer", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        let mut fired = false;
+        for tick in 2..=600 {
+            run_until_tick(&mut app, tick, frame_length);
+            let mut projectiles = app.world.query::<&Projectile>();
+            fired |= projectiles.iter(&app.world).next().is_some();
+        }
+        assert!(fired);
+        let mut score_board = app.world.query::<&ScoreBoard>();
+        assert!(score_board.single(&app.world).player_score > 0);
+    }
+
+    #[test]
+    fn towers_upgrade() {
+        let (mut app, tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        let starting_budget = app.world.resource::<Config>().0.starting_budget;
+
+        let hex_pos = free_tile_next_to_path(&mut app);
+        tx.send(parse_command(&format!("tower {},{} Small", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 2, frame_length);
+        tx.send(parse_command(&format!("upgrade {},{}", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 3, frame_length);
+
+        let mut budget = app.world.query::<&Budget>();
+        let budget = budget.single(&app.world).0;
+        let mut damaging = app.world.query::<&Damaging>();
+        let mut towers = app.world.query::<(&Tower, &Transform)>();
+        let (tower, transform) = towers.single(&app.world);
+        let small = &app.world.resource::<Config>().0.tower_config.tower_types["Small"];
+        let stats = small.stats(1);
+        assert_eq!(tower.level, 1);
+        assert_eq!(tower.cost, small.cost + small.upgrades[0].cost);
+        assert_eq!(transform.scale, Vec3::splat(stats.scale));
+        assert_eq!(budget + tower.cost, starting_budget);
+        assert!(damaging.iter(&app.world).all(|d| d.value == stats.damage));
+    }
+
+    #[test]
+    fn towers_sell_for_refund() {
+        let (mut app, tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+        let starting_budget = app.world.resource::<Config>().0.starting_budget;
+
+        let hex_pos = free_tile_next_to_path(&mut app);
+        tx.send(parse_command(&format!("tower {},{} Medium", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 2, frame_length);
+        tx.send(parse_command(&format!("sell {},{}", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 3, frame_length);
+
+        let mut towers = app.world.query::<&Tower>();
+        assert_eq!(towers.iter(&app.world).count(), 0);
+        let mut has_tower = app.world.query::<&HasTower>();
+        assert_eq!(has_tower.iter(&app.world).count(), 0);
+        let mut damaging = app.world.query::<&Damaging>();
+        assert_eq!(damaging.iter(&app.world).count(), 0);
+        let config = &app.world.resource::<Config>().0.tower_config;
+        let cost = config.tower_types["Medium"].cost;
+        let refund = (cost as f32 * config.refund_ratio) as u32;
+        let mut budget = app.world.query::<&Budget>();
+        assert_eq!(budget.single(&app.world).0, starting_budget - cost + refund);
+    }
+
+    #[test]
+    fn towers_change_targeting() {
+        let (mut app, tx) = headless_app(42);
+        let frame_length = tick_length(&app);
+        run_until_tick(&mut app, 1, frame_length);
+
+        let hex_pos = free_tile_next_to_path(&mut app);
+        tx.send(parse_command(&format!("tower {},{} Archer", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 2, frame_length);
+        let mut targeting = app.world.query::<&TargetingMode>();
+        assert_eq!(*targeting.single(&app.world), TargetingMode::First);
+
+        assert!(parse_command(&format!("target {},{} nearest", hex_pos.x, hex_pos.y)).is_none());
+        tx.send(parse_command(&format!("target {},{} weakest", hex_pos.x, hex_pos.y)).unwrap())
+            .unwrap();
+        run_until_tick(&mut app, 3, frame_length);
+        assert_eq!(*targeting.single(&app.world), TargetingMode::Weakest);
+    }
+}
@@ -1,6 +1,8 @@
 use crate::communication::*;
 use crate::components::*;
+use crate::config::MapGenerator;
 use crate::resources::*;
+use crate::systems::send_command;
 use crate::AppState;
 use crate::CONFIG_PATH;
 use bevy::app::AppExit;
@@ -11,6 +13,7 @@ use bevy_egui::{egui, EguiContexts};
 use tracing::event;
 use tracing::Level;
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn show_ui(
     mut contexts: EguiContexts,
     tiles: Query<(
@@ -21,15 +24,29 @@ pub fn show_ui(
         Option<&Children>,
     )>,
     damaging_base: Query<&DamagingBase>,
+    towers: Query<(&Tower, &TargetingMode)>,
+    goal: Query<&Lives, With<IsGoal>>,
     mut config: ResMut<Config>,
-    mut board_q: Query<(&mut Budget, &mut ScoreBoard, &GameTimer)>,
+    mut board_q: Query<(&mut Budget, &mut ScoreBoard, &GameTimer, &WaveState)>,
     mut selected_tower: ResMut<SelectedTower>,
-    mut restart_channel: EventWriter<Restart>,
+    command_sender: Res<GameCommandSender>,
+    replay: Option<Res<ReplayPlayer>>,
     mut exit: EventWriter<AppExit>,
     mut next_state: ResMut<NextState<AppState>>,
     state: Res<State<AppState>>,
+    mut results: EventReader<CommandResult>,
+    mut last_rejected: Local<Option<CommandError>>,
 ) {
-    let (budget, score_board, game_timer) = board_q.single_mut();
+    if let Some(error) = results
+        .iter()
+        .filter_map(|r| r.result.as_ref().err())
+        .last()
+    {
+        *last_rejected = Some(error.clone());
+    }
+    let (budget, score_board, game_timer, wave_state) = board_q.single_mut();
+    // the game only takes the commands of a replay while it plays
+    let replaying = replay.is_some();
     egui::Window::new("Tower Defense")
         .anchor(Align2::LEFT_TOP, [5.0, 5.0])
         .show(contexts.ctx_mut(), |ui| {
@@ -58,6 +75,58 @@ pub fn show_ui(
                     "no"
                 };
                 ui.label(format!("Damaging base: {}", d_base));
+                let tower = children.and_then(|c| c.iter().find_map(|c| towers.get(*c).ok()));
+                if let Some((tower, targeting)) = tower {
+                    let tower_config = config.0.tower_config.tower_types.get(&tower.tower_type.0);
+                    ui.label(format!(
+                        "Tower: {} level {} ({} damage)",
+                        tower.tower_type.0,
+                        tower.level,
+                        tower_config.map_or("unknown", |t| t.damage_type.as_str())
+                    ));
+                    ui.add_enabled_ui(!replaying, |ui| {
+                        let upgrade =
+                            tower_config.and_then(|t| t.upgrades.get(tower.level as usize));
+                        if let Some(upgrade) = upgrade {
+                            if ui.button(format!("Upgrade ({})", upgrade.cost)).clicked() {
+                                send_command(
+                                    &command_sender,
+                                    TDCommand::Upgrade(UpgradeTower { hex_pos: hex.0 }),
+                                );
+                            }
+                        } else {
+                            ui.label("Fully upgraded");
+                        }
+                        egui::ComboBox::from_label("Targeting")
+                            .selected_text(format!("{:?}", targeting))
+                            .show_ui(ui, |ui| {
+                                for mode in TargetingMode::ALL {
+                                    let selected = mode == *targeting;
+                                    if ui
+                                        .selectable_label(selected, format!("{:?}", mode))
+                                        .clicked()
+                                        && !selected
+                                    {
+                                        send_command(
+                                            &command_sender,
+                                            TDCommand::Target(SetTargeting {
+                                                hex_pos: hex.0,
+                                                mode,
+                                            }),
+                                        );
+                                    }
+                                }
+                            });
+                        let refund =
+                            (tower.cost as f32 * config.0.tower_config.refund_ratio) as u32;
+                        if ui.button(format!("Sell (+{refund})")).clicked() {
+                            send_command(
+                                &command_sender,
+                                TDCommand::Sell(SellTower { hex_pos: hex.0 }),
+                            );
+                        }
+                    });
+                }
             } else {
                 ui.label("None selected".to_string());
             }
@@ -75,6 +144,38 @@ pub fn show_ui(
                 };
                 ui.label("Starting budget");
                 ui.add(egui::Slider::new(&mut config.0.starting_budget, 10..=120));
+                ui.label("Map generator");
+                let generation = &mut config.0.map_generation;
+                egui::ComboBox::from_id_source("map_generator")
+                    .selected_text(format!("{:?}", generation.generator))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut generation.generator,
+                            MapGenerator::Random,
+                            "Random",
+                        );
+                        ui.selectable_value(
+                            &mut generation.generator,
+                            MapGenerator::Noise,
+                            "Noise",
+                        );
+                        ui.selectable_value(
+                            &mut generation.generator,
+                            MapGenerator::CellularAutomata,
+                            "Cellular automata",
+                        );
+                    });
+                ui.label("Mountain ratio");
+                ui.add(egui::Slider::new(
+                    &mut generation.mountain_ratio,
+                    0_f32..=0.8,
+                ));
+                ui.label("Smoothing passes");
+                ui.add(egui::Slider::new(&mut generation.smoothing_passes, 0..=10));
+                ui.checkbox(
+                    &mut generation.ensure_connectivity,
+                    "Connect spawns to goal",
+                );
             });
 
             egui::CollapsingHeader::new("Enemy Config").show(ui, |ui| {
@@ -90,6 +191,15 @@ pub fn show_ui(
                         .speed(0.1)
                         .clamp_range(0.01..=20.),
                 );
+                ui.label("Enemy type speed");
+                for (name, enemy_type) in config.0.enemy_config.enemy_types.iter_mut() {
+                    ui.label(name.as_str());
+                    ui.add(
+                        egui::DragValue::new(&mut enemy_type.speed)
+                            .speed(0.1)
+                            .clamp_range(0.1..=10.),
+                    );
+                }
             });
 
             egui::CollapsingHeader::new("Tower Config").show(ui, |ui| {
@@ -99,22 +209,60 @@ pub fn show_ui(
                         .speed(0.1)
                         .clamp_range(0.01..=20.),
                 );
-                ui.label("Tower cost");
-                for (t_type, config) in config.0.tower_config.tower_type.iter_mut() {
-                    ui.label(format!("{:?}", t_type));
-                    ui.add(
-                        egui::DragValue::new(&mut config.cost)
-                            .speed(0.1)
-                            .clamp_range(1..=100),
-                    );
+                ui.label("Refund ratio");
+                ui.add(egui::Slider::new(
+                    &mut config.0.tower_config.refund_ratio,
+                    0_f32..=1.,
+                ));
+                for (name, config) in config.0.tower_config.tower_types.iter_mut() {
+                    ui.label(name.as_str());
+                    ui.horizontal(|ui| {
+                        ui.label("Cost");
+                        ui.add(
+                            egui::DragValue::new(&mut config.cost)
+                                .speed(0.1)
+                                .clamp_range(1..=100),
+                        );
+                        // read only, selling and upgrading take the damage
+                        // placed towers deal off again by the config
+                        ui.label(format!("Damage: {}", config.damage));
+                        ui.label(format!("Fire rate: {}", config.fire_rate));
+                    });
                 }
             });
+            if let Some(error) = last_rejected.as_ref() {
+                ui.colored_label(egui::Color32::RED, format!("Rejected: {error}"));
+            }
             ui.label(format!("Current budget: {}", budget.0));
+            if let Ok(lives) = goal.get_single() {
+                ui.label(format!("Lives: {}/{}", lives.0, config.0.outcome.lives));
+            }
             ui.label("Score Board");
             ui.horizontal(|ui| {
                 ui.label(format!("Player: {}", score_board.player_score));
                 ui.label(format!("Enemy: {}", score_board.enemy_score));
             });
+            let waves = &config.0.waves;
+            if let Some(wave) = waves.get(wave_state.wave) {
+                ui.label(format!("Wave: {}/{}", wave_state.wave + 1, waves.len()));
+                if wave_state.spawned < wave.enemy_count() {
+                    ui.label(format!(
+                        "Enemies spawned: {}/{}",
+                        wave_state.spawned,
+                        wave.enemy_count()
+                    ));
+                } else if let Some(next_wave) = waves.get(wave_state.wave + 1) {
+                    ui.label(format!(
+                        "Next wave: {} enemies in {:.0}s",
+                        next_wave.enemy_count(),
+                        wave_state.timer.remaining_secs()
+                    ));
+                } else {
+                    ui.label("Last wave");
+                }
+            } else if !waves.is_empty() {
+                ui.label("All waves spawned");
+            }
             ui.horizontal(|ui| {
                 if ui.button("Export config").clicked() {
                     let game_config = config.0.clone();
@@ -124,6 +272,30 @@ pub fn show_ui(
                     }
                 }
             });
+            ui.horizontal(|ui| {
+                ui.set_enabled(!replaying);
+                ui.label("Save file");
+                ui.text_edit_singleline(&mut config.0.save_path);
+            });
+            ui.horizontal(|ui| {
+                ui.set_enabled(!replaying);
+                if ui.button("Save game").clicked() {
+                    send_command(
+                        &command_sender,
+                        TDCommand::Save(SaveGame {
+                            path: config.0.save_path.clone(),
+                        }),
+                    );
+                }
+                if ui.button("Load game").clicked() {
+                    send_command(
+                        &command_sender,
+                        TDCommand::Load(LoadGame {
+                            path: config.0.save_path.clone(),
+                        }),
+                    );
+                }
+            });
             ui.horizontal(|ui| {
                 if ui.button("Pause").clicked() {
                     if state.0 == AppState::Pause {
@@ -139,8 +311,9 @@ pub fn show_ui(
                 }
             });
             ui.horizontal(|ui| {
+                ui.set_enabled(!replaying);
                 if ui.button("Restart Board").clicked() {
-                    restart_channel.send(Restart);
+                    send_command(&command_sender, TDCommand::Restart(Restart));
                 }
             });
             ui.horizontal(|ui| {
@@ -156,23 +329,15 @@ pub fn show_ui(
         .show(contexts.ctx_mut(), |ui| {
             ui.label("Selected tower");
             egui::ComboBox::from_label("")
-                .selected_text(format!("{:?}", selected_tower.selected))
+                .selected_text(selected_tower.selected.0.as_str())
                 .show_ui(ui, |ui| {
-                    ui.selectable_value(
-                        &mut selected_tower.selected,
-                        TowerType::Small,
-                        "Small tower",
-                    );
-                    ui.selectable_value(
-                        &mut selected_tower.selected,
-                        TowerType::Medium,
-                        "Medium tower",
-                    );
-                    ui.selectable_value(
-                        &mut selected_tower.selected,
-                        TowerType::Large,
-                        "Large tower",
-                    );
+                    for (name, tower_config) in config.0.tower_config.tower_types.iter() {
+                        ui.selectable_value(
+                            &mut selected_tower.selected,
+                            TowerType(name.clone()),
+                            format!("{name} ({})", tower_config.cost),
+                        );
+                    }
                 });
         });
 
@@ -0,0 +1,104 @@
+// Helpers of the tests driving a headless game by hand
+use crate::communication::parse_command;
+use crate::communication::TDCommand;
+use crate::components::Budget;
+use crate::components::Coords;
+use crate::components::Enemy;
+use crate::components::IsGoal;
+use crate::components::IsSpawn;
+use crate::components::OnPath;
+use crate::components::ScoreBoard;
+use crate::config::GameConfig;
+use crate::resources::SimulationTick;
+use crate::setup_tower_defense_headless;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use crossbeam_channel::Sender;
+use hexx::Hex;
+use std::time::Duration;
+use std::time::Instant;
+
+pub fn headless_app(seed: u64) -> (App, Sender<TDCommand>) {
+    let config = GameConfig {
+        seed: Some(seed),
+        ..default()
+    };
+    let (mut app, tx) = setup_tower_defense_headless(config);
+    app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
+    (app, tx)
+}
+
+// advances the app by frames of `frame_length` until `tick` is reached, the frames
+// are cut short near it as ticks waiting for paths put them out of step
+pub fn run_until_tick(app: &mut App, tick: u64, frame_length: Duration) {
+    loop {
+        let remaining = tick.saturating_sub(app.world.resource::<SimulationTick>().0);
+        if remaining == 0 {
+            break;
+        }
+        let frame_length = frame_length.min(tick_length(app) * remaining as u32);
+        let mut strategy = app.world.resource_mut::<TimeUpdateStrategy>();
+        if let TimeUpdateStrategy::ManualInstant(instant) = strategy.as_mut() {
+            *instant += frame_length;
+        }
+        app.update();
+    }
+    assert_eq!(app.world.resource::<SimulationTick>().0, tick);
+}
+
+// advances the app by a frame, for states the simulation tick stands still in
+pub fn run_frame(app: &mut App, frame_length: Duration) {
+    let mut strategy = app.world.resource_mut::<TimeUpdateStrategy>();
+    if let TimeUpdateStrategy::ManualInstant(instant) = strategy.as_mut() {
+        *instant += frame_length;
+    }
+    app.update();
+}
+
+pub fn tick_length(app: &App) -> Duration {
+    app.world.resource::<FixedTime>().period
+}
+
+// a tile that a tower can be placed on and that damages the path
+pub fn free_tile_next_to_path(app: &mut App) -> Hex {
+    let mut path_tiles = app.world.query_filtered::<&Coords, With<OnPath>>();
+    let path_tiles: Vec<Hex> = path_tiles.iter(&app.world).map(|c| c.0).collect();
+    let mut tiles = app
+        .world
+        .query_filtered::<&Coords, (Without<OnPath>, Without<IsGoal>, Without<IsSpawn>)>();
+    let mut free_tiles: Vec<Hex> = tiles
+        .iter(&app.world)
+        .map(|c| c.0)
+        .filter(|hex| hex.all_neighbors().iter().any(|n| path_tiles.contains(n)))
+        .collect();
+    free_tiles.sort_by_key(|hex| (hex.x, hex.y));
+    free_tiles[0]
+}
+
+// toggles the first path tiles on ticks 60, 90 and 120
+pub fn send_path_toggles(app: &mut App, tx: &Sender<TDCommand>, frame_length: Duration) {
+    let mut tiles = app
+        .world
+        .query_filtered::<&Coords, (With<OnPath>, Without<IsSpawn>)>();
+    let mut path_tiles: Vec<Hex> = tiles.iter(&app.world).map(|c| c.0).collect();
+    path_tiles.sort_by_key(|hex| (hex.x, hex.y));
+    for (i, hex) in path_tiles.iter().take(3).enumerate() {
+        run_until_tick(app, 60 + i as u64 * 30, frame_length);
+        tx.send(parse_command(&format!("toggle {},{}", hex.x, hex.y)).unwrap())
+            .unwrap();
+    }
+}
+
+// budget, score board and enemy positions
+pub fn board_state(app: &mut App) -> (u32, u32, u32, Vec<(u32, u32)>) {
+    let mut board = app.world.query::<(&Budget, &ScoreBoard)>();
+    let (budget, score_board) = board.single(&app.world);
+    let (budget, player_score, enemy_score) =
+        (budget.0, score_board.player_score, score_board.enemy_score);
+    let mut enemies = app.world.query_filtered::<&Transform, With<Enemy>>();
+    let positions = enemies
+        .iter(&app.world)
+        .map(|t| (t.translation.x.to_bits(), t.translation.y.to_bits()))
+        .collect();
+    (budget, player_score, enemy_score, positions)
+}
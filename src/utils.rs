@@ -31,6 +31,7 @@ pub enum MeshType {
     Hex,
     Enemy,
     Tower,
+    Projectile,
 }
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub enum DamageLevel {